@@ -0,0 +1,53 @@
+// Cards with several acceptable answers.
+//
+// A back may list alternatives separated by `|`, e.g. `glad | joyful | cheerful`.
+// Flip mode shows every alternative; typed-answer mode accepts any of them.
+
+use crate::unicode;
+
+/// The acceptable answers listed on a card's back, in order.
+pub fn alternatives(back: &str) -> Vec<&str> {
+    back.split('|').map(str::trim).filter(|a| !a.is_empty()).collect()
+}
+
+/// The back as shown after flipping: one alternative per line.
+pub fn display(back: &str) -> String {
+    let alternatives = alternatives(back);
+    if alternatives.len() < 2 {
+        return back.to_string();
+    }
+    alternatives.join("\n")
+}
+
+/// The form typed answers are compared in: ignoring case, surrounding and
+/// repeated whitespace, and Unicode composition and typographic punctuation;
+/// see `unicode::fold`.
+pub fn comparable(answer: &str) -> String {
+    unicode::fold(answer).split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// Whether `typed` matches any of the back's alternatives.
+pub fn accepts(back: &str, typed: &str) -> bool {
+    let typed = comparable(typed);
+    alternatives(back).iter().any(|a| comparable(a) == typed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alternatives_split_on_pipe() {
+        assert_eq!(alternatives("glad | joyful |cheerful"), vec!["glad", "joyful", "cheerful"]);
+        assert_eq!(alternatives("\nParis\n"), vec!["Paris"]);
+        assert_eq!(display("glad | joyful"), "glad\njoyful");
+    }
+
+    #[test]
+    fn accepts_any_alternative() {
+        assert!(accepts("glad | joyful", "Joyful"));
+        assert!(accepts("New  York", " new york "));
+        assert!(!accepts("glad | joyful", "sad"));
+        assert!(accepts("caf\u{e9} | l\u{2019}\u{e9}t\u{e9}", "l'e\u{301}te\u{301}"));
+    }
+}
@@ -0,0 +1,49 @@
+// Helpers for pulling flags out of the raw argument list.
+//
+// Flags are removed from `args` as they are read, so commands can keep
+// indexing positional arguments (`args[2]`, `args[3]`, ...) no matter where
+// the flags were placed on the command line.
+
+use std::str::FromStr;
+
+/// Removes every occurrence of `name` from `args` and returns whether it was present.
+pub fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    let before = args.len();
+    args.retain(|arg| arg != name);
+    args.len() != before
+}
+
+/// Removes `name` and its value from `args`, accepting both `--name value` and `--name=value`.
+pub fn take_value(args: &mut Vec<String>, name: &str) -> Result<Option<String>, String> {
+    let prefix = format!("{}=", name);
+
+    for i in 0..args.len() {
+        if args[i] == name {
+            if i + 1 >= args.len() {
+                return Err(format!("Missing value for {}", name));
+            }
+            let value = args.remove(i + 1);
+            args.remove(i);
+            return Ok(Some(value));
+        }
+
+        if let Some(value) = args[i].strip_prefix(&prefix) {
+            let value = value.to_string();
+            args.remove(i);
+            return Ok(Some(value));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Like `take_value`, but parses the value into `T`.
+pub fn take_parsed<T: FromStr>(args: &mut Vec<String>, name: &str) -> Result<Option<T>, String> {
+    match take_value(args, name)? {
+        Some(value) => match value.parse() {
+            Ok(parsed) => Ok(Some(parsed)),
+            Err(_) => Err(format!("Invalid value for {}: {}", name, value)),
+        },
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,357 @@
+// `compare`: two decks, or two schedulers, side by side.
+//
+// Comparing decks reads what's recorded: review accuracy over a window, and
+// the cards' current maturity, estimated retention and due counts. Comparing
+// schedulers replays each card's logged reviews through both of them from a
+// new card's state, so they're judged on the same answers. The log only
+// records pass or fail, replayed as Good and Again, and reviews logged
+// without a timestamp can't be replayed.
+
+use rusqlite::{params, Connection, Result};
+use chrono::{Days, NaiveDate, NaiveDateTime};
+use log::debug;
+
+use crate::args::take_parsed;
+use crate::config::Config;
+use crate::report::{mean_retention, MATURE_LEVEL};
+use crate::scheduler::{level_to_date, schedule, CardState, Grade, Scheduler, INITIAL_EASE};
+use crate::{get_deck_id, get_deck_name, suspension_sql, today, DueFilter};
+
+#[derive(Clone, Copy)]
+enum Unit {
+    Count,
+    Percent,
+    Days,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Better {
+    Higher,
+    Lower,
+    // Neither side is better for having more.
+    Neither,
+}
+
+struct Metric {
+    name: &'static str,
+    a: Option<f64>,
+    b: Option<f64>,
+    unit: Unit,
+    better: Better,
+}
+
+fn format_value(value: Option<f64>, unit: Unit) -> String {
+    match (value, unit) {
+        (None, _) => "-".to_string(),
+        (Some(value), Unit::Count) => format!("{}", value),
+        (Some(value), Unit::Percent) => format!("{:.0}%", value * 100.0),
+        (Some(value), Unit::Days) => format!("{:.1}d", value),
+    }
+}
+
+fn format_difference(a: Option<f64>, b: Option<f64>, unit: Unit) -> String {
+    match (a, b, unit) {
+        (Some(a), Some(b), Unit::Percent) => format!("{:+.0} pts", (b - a) * 100.0),
+        (Some(a), Some(b), Unit::Count) => format!("{:+}", b - a),
+        (Some(a), Some(b), Unit::Days) => format!("{:+.1}d", b - a),
+        _ => "-".to_string(),
+    }
+}
+
+// Which side is better on this metric: `Some(true)` for b, `None` for a tie
+// or a metric without a better side.
+fn b_is_better(metric: &Metric) -> Option<bool> {
+    let (a, b) = (metric.a?, metric.b?);
+    if a == b {
+        return None;
+    }
+    match metric.better {
+        Better::Higher => Some(b > a),
+        Better::Lower => Some(b < a),
+        Better::Neither => None,
+    }
+}
+
+// The table for two columns named `a` and `b`, with the better value of each
+// metric marked `*`.
+fn table(a: &str, b: &str, metrics: &[Metric]) -> String {
+    let width = a.chars().count().max(b.chars().count()).max(8) + 1;
+    // Values are followed by their mark, so the names end a column earlier.
+    let mut out = format!("{:<16}  {:>w$}  {:>w$}  difference\n", "", format!("{} ", a), format!("{} ", b), w = width);
+    for metric in metrics {
+        let better = b_is_better(metric);
+        let mark = |is_b: bool| if better == Some(is_b) { "*" } else { " " };
+        out.push_str(&format!(
+            "{:<16}  {:>w$}  {:>w$}  {}\n",
+            metric.name,
+            format!("{}{}", format_value(metric.a, metric.unit), mark(false)),
+            format!("{}{}", format_value(metric.b, metric.unit), mark(true)),
+            format_difference(metric.a, metric.b, metric.unit),
+            w = width,
+        ));
+    }
+    out
+}
+
+struct DeckOutcome {
+    cards: i32,
+    mature: i32,
+    due: i32,
+    reviews: i32,
+    correct: i32,
+    retention: Option<f64>,
+}
+
+fn get_deck_outcome(conn: &Connection, deck_id: i32, since: Option<NaiveDateTime>, today: &NaiveDate) -> Result<DeckOutcome> {
+    debug!("get_deck_outcome: deck_id={} since={:?}", deck_id, since);
+    let sql = format!(
+        "SELECT COUNT(*), COALESCE(SUM(level >= ?2), 0), COALESCE(SUM({} AND {}), 0) FROM flashcards WHERE deck_id = ?1",
+        DueFilter::Due.sql("?3"),
+        suspension_sql(false),
+    );
+    let (cards, mature, due) = conn.query_row(&sql, params![deck_id, MATURE_LEVEL, today], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    let (reviews, correct) = conn.query_row(
+        "SELECT COUNT(*), COALESCE(SUM(l.answer), 0)
+        FROM flashcard_log l JOIN flashcards f ON f.id = l.question_id
+        WHERE f.deck_id = ?1 AND (?2 IS NULL OR l.reviewed_at >= ?2)",
+        params![deck_id, since],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    let retention = mean_retention(conn, deck_id, today)?;
+    Ok(DeckOutcome { cards, mature, due, reviews, correct, retention })
+}
+
+fn deck_metrics(a: &DeckOutcome, b: &DeckOutcome) -> Vec<Metric> {
+    let accuracy = |o: &DeckOutcome| (o.reviews > 0).then(|| o.correct as f64 / o.reviews as f64);
+    let mature = |o: &DeckOutcome| (o.cards > 0).then(|| o.mature as f64 / o.cards as f64);
+    vec![
+        Metric { name: "cards", a: Some(a.cards as f64), b: Some(b.cards as f64), unit: Unit::Count, better: Better::Neither },
+        Metric { name: "reviews", a: Some(a.reviews as f64), b: Some(b.reviews as f64), unit: Unit::Count, better: Better::Neither },
+        Metric { name: "accuracy", a: accuracy(a), b: accuracy(b), unit: Unit::Percent, better: Better::Higher },
+        Metric { name: "mature", a: mature(a), b: mature(b), unit: Unit::Percent, better: Better::Higher },
+        Metric { name: "est. retention", a: a.retention, b: b.retention, unit: Unit::Percent, better: Better::Higher },
+        Metric { name: "due today", a: Some(a.due as f64), b: Some(b.due as f64), unit: Unit::Count, better: Better::Neither },
+    ]
+}
+
+/// A card's logged reviews replayed through one scheduler.
+struct Replay {
+    state: CardState,
+    next: NaiveDate,
+    // Reviews logged before the scheduler would have made the card due.
+    early: usize,
+}
+
+fn replay(scheduler: Scheduler, reviews: &[(NaiveDate, bool)], min_interval_days: u32) -> Option<Replay> {
+    let (first, _) = reviews.first()?;
+    let mut replay = Replay {
+        state: CardState { level: 1, ease_factor: INITIAL_EASE, repetitions: 0, interval_days: 0 },
+        next: *first,
+        early: 0,
+    };
+    for (date, correct) in reviews {
+        if *date < replay.next {
+            replay.early += 1;
+        }
+        let grade = if *correct { Grade::Good } else { Grade::Again };
+        let review = schedule(scheduler, *date, &replay.state, grade, min_interval_days);
+        replay.state = CardState { level: review.level, ease_factor: review.ease_factor, repetitions: review.repetitions, interval_days: review.interval_days };
+        replay.next = review.next;
+    }
+    Some(replay)
+}
+
+// Each card's timestamped reviews, oldest first, in one deck or all of them.
+fn get_review_history(conn: &Connection, deck_id: Option<i32>) -> Result<Vec<Vec<(NaiveDate, bool)>>> {
+    debug!("get_review_history: deck_id={:?}", deck_id);
+    let mut stmt = conn.prepare(
+        "SELECT l.question_id, l.reviewed_at, l.answer
+        FROM flashcard_log l JOIN flashcards f ON f.id = l.question_id
+        WHERE l.reviewed_at IS NOT NULL AND (?1 IS NULL OR f.deck_id = ?1)
+        ORDER BY l.question_id, l.reviewed_at"
+    )?;
+    let rows = stmt.query_map(params![deck_id], |row| {
+        Ok((row.get::<_, i32>(0)?, row.get::<_, NaiveDateTime>(1)?.date(), row.get::<_, bool>(2)?))
+    })?.collect::<Result<Vec<_>>>()?;
+
+    let mut cards: Vec<(i32, Vec<(NaiveDate, bool)>)> = Vec::new();
+    for (id, date, correct) in rows {
+        match cards.last_mut() {
+            Some((last, reviews)) if *last == id => reviews.push((date, correct)),
+            _ => cards.push((id, vec![(date, correct)])),
+        }
+    }
+    Ok(cards.into_iter().map(|(_, reviews)| reviews).collect())
+}
+
+fn scheduler_metrics(a: &[Replay], b: &[Replay], today: &NaiveDate) -> Vec<Metric> {
+    let mean_interval = |replays: &[Replay]| {
+        (!replays.is_empty()).then(|| replays.iter().map(|r| r.state.interval_days as f64).sum::<f64>() / replays.len() as f64)
+    };
+    let mature = |replays: &[Replay]| {
+        (!replays.is_empty()).then(|| {
+            let mature = replays.iter().filter(|r| r.state.interval_days >= level_to_date(MATURE_LEVEL)).count();
+            mature as f64 / replays.len() as f64
+        })
+    };
+    let due = |replays: &[Replay]| Some(replays.iter().filter(|r| r.next <= *today).count() as f64);
+    let early = |replays: &[Replay]| Some(replays.iter().map(|r| r.early).sum::<usize>() as f64);
+    vec![
+        Metric { name: "mean interval", a: mean_interval(a), b: mean_interval(b), unit: Unit::Days, better: Better::Neither },
+        Metric { name: "mature", a: mature(a), b: mature(b), unit: Unit::Percent, better: Better::Neither },
+        Metric { name: "due today", a: due(a), b: due(b), unit: Unit::Count, better: Better::Lower },
+        Metric { name: "early reviews", a: early(a), b: early(b), unit: Unit::Count, better: Better::Lower },
+    ]
+}
+
+// The deck named `name`, checking that one given by id exists.
+fn deck(conn: &Connection, name: &str) -> std::result::Result<(i32, String), String> {
+    match get_deck_id(conn, name).and_then(|id| Ok((id, get_deck_name(conn, id)?))) {
+        Ok(deck) => Ok(deck),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Err(format!("No deck with name or id {}", name)),
+        Err(e) => Err(format!("Error getting deck id: {}", e)),
+    }
+}
+
+/// `compare <deck_a> <deck_b> [--days N]` or
+/// `compare --schedulers <a> <b> [deck_name]`
+///
+/// Decks are compared on accuracy and review counts, over the last N days
+/// with `--days`, and on the share of mature cards (see `snapshot`), mean
+/// estimated retention (see `retention`) and due cards as they are today.
+///
+/// `--schedulers sm2 leitner` replays the review history of one deck, or of
+/// every deck, through both schedulers and compares where cards end up:
+/// their mean interval, the share with mature (25+ day) intervals, how many
+/// would be due today, and how many logged reviews came before the scheduler
+/// would have asked for them. Longer intervals mean less work, but only for
+/// as long as the answers stay right, which a replay can't tell.
+///
+/// The better value of each metric, where there is one, is marked `*`, and
+/// the last column is the second value minus the first.
+pub fn compare(conn: &Connection, config: &Config, args: &[String]) {
+    let mut args = args.to_vec();
+    let days: Option<u64> = match take_parsed(&mut args, "--days") {
+        Ok(days) => days,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    let today = today();
+
+    if let Some(i) = args.iter().position(|arg| arg == "--schedulers") {
+        if days.is_some() {
+            fail!("--days doesn't apply to --schedulers, which replays every review");
+            return;
+        }
+        if args.len() < i + 3 {
+            fail!("Missing schedulers: --schedulers <a> <b>, as in --schedulers sm2 leitner");
+            return;
+        }
+        let names: Vec<String> = args.drain(i..i + 3).skip(1).collect();
+        let mut schedulers = Vec::new();
+        for name in &names {
+            match Scheduler::parse(name) {
+                Some(scheduler) => schedulers.push(scheduler),
+                None => {
+                    fail!("Unknown scheduler: {} (supported: sm2, leitner)", name);
+                    return;
+                }
+            }
+        }
+
+        let deck_id = match args.get(2).map(|name| deck(conn, name)).transpose() {
+            Ok(deck) => deck.map(|(id, _)| id),
+            Err(e) => {
+                fail!("{}", e);
+                return;
+            }
+        };
+        let history = match get_review_history(conn, deck_id) {
+            Ok(history) => history,
+            Err(e) => {
+                fail!("Error reading review history: {}", e);
+                return;
+            }
+        };
+        if history.is_empty() {
+            println!("No timestamped reviews to replay");
+            return;
+        }
+
+        let replays: Vec<Vec<Replay>> = schedulers.iter()
+            .map(|&scheduler| history.iter().filter_map(|reviews| replay(scheduler, reviews, config.min_interval_days())).collect())
+            .collect();
+        let reviews: usize = history.iter().map(Vec::len).sum();
+        println!("Replayed {} reviews of {} cards", reviews, history.len());
+        print!("{}", table(&names[0], &names[1], &scheduler_metrics(&replays[0], &replays[1], &today)));
+        return;
+    }
+
+    if args.len() < 4 {
+        fail!("Missing <deck_a> <deck_b>");
+        return;
+    }
+
+    let mut outcomes = Vec::new();
+    let mut names = Vec::new();
+    for name in &args[2..4] {
+        let (id, name) = match deck(conn, name) {
+            Ok(deck) => deck,
+            Err(e) => {
+                fail!("{}", e);
+                return;
+            }
+        };
+        let since = days.map(|days| today.checked_sub_days(Days::new(days)).unwrap_or(NaiveDate::MIN).and_hms_opt(0, 0, 0).unwrap());
+        match get_deck_outcome(conn, id, since, &today) {
+            Ok(outcome) => outcomes.push(outcome),
+            Err(e) => {
+                fail!("Error reading deck {}: {}", name, e);
+                return;
+            }
+        }
+        names.push(name);
+    }
+
+    if let Some(days) = days {
+        println!("Reviews from the last {} days", days);
+    }
+    print!("{}", table(&names[0], &names[1], &deck_metrics(&outcomes[0], &outcomes[1])));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 1, day).unwrap()
+    }
+
+    #[test]
+    fn replays_count_reviews_before_the_card_was_due() {
+        // Leitner: 4 days after the first pass, 10 after the second.
+        let reviews = [(date(1), true), (date(3), true), (date(5), false)];
+        let replay = replay(Scheduler::Leitner, &reviews, 1).unwrap();
+        assert_eq!(replay.early, 2);
+        assert_eq!(replay.state.level, 2);
+        assert_eq!(replay.next, date(15));
+        assert!(super::replay(Scheduler::Sm2, &[], 1).is_none());
+    }
+
+    #[test]
+    fn tables_mark_the_better_value() {
+        let metrics = [
+            Metric { name: "accuracy", a: Some(0.8), b: Some(0.9), unit: Unit::Percent, better: Better::Higher },
+            Metric { name: "cards", a: Some(3.0), b: Some(5.0), unit: Unit::Count, better: Better::Neither },
+            Metric { name: "mature", a: None, b: Some(0.5), unit: Unit::Percent, better: Better::Higher },
+        ];
+        let table = table("Geo", "Hist", &metrics);
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[1].contains(" 80%  ") && lines[1].contains("90%*") && lines[1].ends_with("+10 pts"));
+        assert!(!lines[2].contains('*') && lines[2].ends_with("+2"));
+        assert!(lines[3].ends_with("-"));
+    }
+}
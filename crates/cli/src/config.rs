@@ -0,0 +1,471 @@
+// Settings, layered from lowest to highest precedence:
+//
+//   1. built-in defaults
+//   2. the `settings` table (`flash config set <key> <value>`)
+//   3. environment variables (`FLASH_<KEY>`)
+//   4. command line flags
+//
+// Every setting is declared once in `KEYS`; `Config::load` resolves them all
+// and keeps track of where each effective value came from.
+
+use std::fmt;
+use std::time::Duration;
+
+use rusqlite::{params, Connection, Result};
+use log::debug;
+
+use crate::args::take_value;
+use crate::parse::EmptySides;
+use crate::quiz::{AutosaveInterval, TimeoutAction};
+use crate::scheduler::Scheduler;
+use crate::table_exists;
+
+struct Key {
+    name: &'static str,
+    env: &'static str,
+    flag: &'static str,
+    default: &'static str,
+    validate: fn(&str) -> std::result::Result<(), String>,
+}
+
+fn validate_min_interval(value: &str) -> std::result::Result<(), String> {
+    match value.parse::<u32>() {
+        Ok(days) if days >= 1 => Ok(()),
+        _ => Err(format!("must be a whole number of days >= 1, got {}", value)),
+    }
+}
+
+fn validate_empty_sides(value: &str) -> std::result::Result<(), String> {
+    match EmptySides::parse(value) {
+        Some(_) => Ok(()),
+        None => Err(format!("must be reject or warn, got {}", value)),
+    }
+}
+
+fn validate_sessions_per_day(value: &str) -> std::result::Result<(), String> {
+    match value.parse::<u32>() {
+        Ok(sessions) if sessions >= 1 => Ok(()),
+        _ => Err(format!("must be a whole number >= 1, got {}", value)),
+    }
+}
+
+fn validate_grade_timeout(value: &str) -> std::result::Result<(), String> {
+    match value.parse::<u32>() {
+        Ok(_) => Ok(()),
+        _ => Err(format!("must be a whole number of seconds, got {}", value)),
+    }
+}
+
+fn validate_grade_timeout_action(value: &str) -> std::result::Result<(), String> {
+    match TimeoutAction::parse(value) {
+        Some(_) => Ok(()),
+        None => Err(format!("must be fail, skip or reveal, got {}", value)),
+    }
+}
+
+fn validate_adaptive_threshold(value: &str) -> std::result::Result<(), String> {
+    match value.parse::<f64>() {
+        Ok(threshold) if (0.0..=1.0).contains(&threshold) => Ok(()),
+        _ => Err(format!("must be between 0 and 1, got {}", value)),
+    }
+}
+
+fn validate_adaptive_window(value: &str) -> std::result::Result<(), String> {
+    match value.parse::<usize>() {
+        Ok(cards) if cards >= 1 => Ok(()),
+        _ => Err(format!("must be a whole number of cards >= 1, got {}", value)),
+    }
+}
+
+fn validate_autosave_interval(value: &str) -> std::result::Result<(), String> {
+    match AutosaveInterval::parse(value) {
+        Some(_) => Ok(()),
+        None => Err(format!("must be a number of cards >= 1, or of seconds like 30s, got {}", value)),
+    }
+}
+
+fn validate_scheduler(value: &str) -> std::result::Result<(), String> {
+    match Scheduler::parse(value) {
+        Some(_) => Ok(()),
+        None => Err(format!("must be sm2 or leitner, got {}", value)),
+    }
+}
+
+const KEYS: &[Key] = &[
+    // Floor for the interval of a normal review. It only clamps the next
+    // date written by the scheduler; relearning a card again within the same
+    // session (learning steps) is not affected.
+    Key {
+        name: "min_interval_days",
+        env: "FLASH_MIN_INTERVAL_DAYS",
+        flag: "--min-interval",
+        default: "1",
+        validate: validate_min_interval,
+    },
+    // Cards whose trimmed front or back is empty: `reject` them like other
+    // malformed cards, or `warn` and keep them.
+    Key {
+        name: "empty_sides",
+        env: "FLASH_EMPTY_SIDES",
+        flag: "--empty-sides",
+        default: "reject",
+        validate: validate_empty_sides,
+    },
+    // How many sittings a day `quiz --limit-by-time-of-day` spreads the due
+    // cards over.
+    Key {
+        name: "sessions_per_day",
+        env: "FLASH_SESSIONS_PER_DAY",
+        flag: "--sessions-per-day",
+        default: "3",
+        validate: validate_sessions_per_day,
+    },
+    // Seconds a card may stay unanswered in an interactive quiz, counted
+    // from when its front is shown; 0 turns the timeout off.
+    Key {
+        name: "grade_timeout",
+        env: "FLASH_GRADE_TIMEOUT",
+        flag: "--grade-timeout",
+        default: "0",
+        validate: validate_grade_timeout,
+    },
+    // What a timeout does: `fail` grades the card Again, `skip` moves on
+    // without grading it (it stays due), and `reveal` shows the back and
+    // then waits for a grade as usual.
+    Key {
+        name: "grade_timeout_action",
+        env: "FLASH_GRADE_TIMEOUT_ACTION",
+        flag: "--grade-timeout-action",
+        default: "fail",
+        validate: validate_grade_timeout_action,
+    },
+    // `quiz --difficulty-adaptive-limit` ends the session once accuracy over
+    // the last `adaptive_window` cards drops below `adaptive_threshold`.
+    Key {
+        name: "adaptive_threshold",
+        env: "FLASH_ADAPTIVE_THRESHOLD",
+        flag: "--adaptive-threshold",
+        default: "0.5",
+        validate: validate_adaptive_threshold,
+    },
+    Key {
+        name: "adaptive_window",
+        env: "FLASH_ADAPTIVE_WINDOW",
+        flag: "--adaptive-window",
+        default: "10",
+        validate: validate_adaptive_window,
+    },
+    // How often a quiz commits its grades: every N cards, or every N seconds
+    // as `Ns`. Batching saves a disk sync per card on slow storage; the cost
+    // is that a crash loses the grades since the last commit.
+    Key {
+        name: "autosave_interval",
+        env: "FLASH_AUTOSAVE_INTERVAL",
+        flag: "--autosave-interval",
+        default: "1",
+        validate: validate_autosave_interval,
+    },
+    // How reviews are scheduled: `sm2` adapts each card's intervals to how
+    // it's graded; `leitner` is the old fixed ladder of 1, 4, 10, 25 and 50
+    // days by level.
+    Key {
+        name: "scheduler",
+        env: "FLASH_SCHEDULER",
+        flag: "--scheduler",
+        default: "sm2",
+        validate: validate_scheduler,
+    },
+];
+
+/// Where `main` looks for the database path; see `db_path`.
+pub const DB_ENV: &str = "FLASH_DB";
+
+// `FLASH_` variables that aren't settings, so `validate` doesn't warn about them.
+const OTHER_ENV: &[&str] = &[DB_ENV];
+
+fn find_key(name: &str) -> Option<&'static Key> {
+    KEYS.iter().find(|key| key.name == name)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Source {
+    Default,
+    Database,
+    Env,
+    Flag,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::Database => write!(f, "database"),
+            Source::Env => write!(f, "env"),
+            Source::Flag => write!(f, "flag"),
+        }
+    }
+}
+
+pub struct Setting {
+    pub name: &'static str,
+    pub value: String,
+    pub source: Source,
+}
+
+pub struct Config {
+    settings: Vec<Setting>,
+}
+
+fn get_stored_settings(conn: &Connection) -> Result<Vec<(String, String)>> {
+    // Before `init` there is no settings table; everything is a default.
+    if !table_exists(conn, "settings")? {
+        return Ok(Vec::new());
+    }
+
+    debug!("get_stored_settings");
+    let mut stmt = conn.prepare("SELECT key, value FROM settings ORDER BY key")?;
+    let settings = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?.collect();
+    settings
+}
+
+fn set_stored_setting(conn: &Connection, key: &str, value: &str) -> Result<()> {
+    debug!("set_stored_setting: key={} value={}", key, value);
+    conn.execute(
+        "INSERT INTO settings (key, value) VALUES (?1, ?2) ON CONFLICT(key) DO UPDATE SET value = ?2",
+        params![key, value],
+    )?;
+    Ok(())
+}
+
+fn delete_stored_setting(conn: &Connection, key: &str) -> Result<usize> {
+    debug!("delete_stored_setting: key={}", key);
+    conn.execute("DELETE FROM settings WHERE key = ?1", params![key])
+}
+
+// Resolves every setting without stopping at invalid values; each setting
+// comes back with the validation error for its effective value, if any.
+fn resolve(stored: &[(String, String)], args: &mut Vec<String>) -> std::result::Result<Vec<(Setting, Option<String>)>, String> {
+    let mut resolved = Vec::new();
+    for key in KEYS {
+        let mut setting = Setting { name: key.name, value: key.default.to_string(), source: Source::Default };
+
+        if let Some((_, value)) = stored.iter().find(|(name, _)| name == key.name) {
+            setting.value = value.clone();
+            setting.source = Source::Database;
+        }
+        if let Ok(value) = std::env::var(key.env) {
+            setting.value = value;
+            setting.source = Source::Env;
+        }
+        if let Some(value) = take_value(args, key.flag)? {
+            setting.value = value;
+            setting.source = Source::Flag;
+        }
+
+        debug!("config: {}={} ({})", setting.name, setting.value, setting.source);
+        let error = (key.validate)(&setting.value).err();
+        resolved.push((setting, error));
+    }
+    Ok(resolved)
+}
+
+impl Config {
+    /// Resolves every setting, taking any setting flags out of `args`.
+    pub fn load(conn: &Connection, args: &mut Vec<String>) -> std::result::Result<Config, String> {
+        let stored = get_stored_settings(conn).map_err(|e| format!("Error reading settings: {}", e))?;
+
+        let mut settings = Vec::new();
+        for (setting, error) in resolve(&stored, args)? {
+            if let Some(e) = error {
+                return Err(format!("Invalid {} (from {}): {}", setting.name, setting.source, e));
+            }
+            settings.push(setting);
+        }
+
+        Ok(Config { settings })
+    }
+
+    fn get(&self, name: &str) -> &str {
+        // Every key in KEYS is resolved by `load`, so this can't miss.
+        &self.settings.iter().find(|setting| setting.name == name).unwrap().value
+    }
+
+    pub fn min_interval_days(&self) -> u32 {
+        self.get("min_interval_days").parse().unwrap()
+    }
+
+    pub fn sessions_per_day(&self) -> u32 {
+        self.get("sessions_per_day").parse().unwrap()
+    }
+
+    pub fn empty_sides(&self) -> EmptySides {
+        EmptySides::parse(self.get("empty_sides")).unwrap()
+    }
+
+    pub fn grade_timeout(&self) -> Option<Duration> {
+        match self.get("grade_timeout").parse().unwrap() {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    pub fn grade_timeout_action(&self) -> TimeoutAction {
+        TimeoutAction::parse(self.get("grade_timeout_action")).unwrap()
+    }
+
+    pub fn adaptive_threshold(&self) -> f64 {
+        self.get("adaptive_threshold").parse().unwrap()
+    }
+
+    pub fn adaptive_window(&self) -> usize {
+        self.get("adaptive_window").parse().unwrap()
+    }
+
+    pub fn autosave_interval(&self) -> AutosaveInterval {
+        AutosaveInterval::parse(self.get("autosave_interval")).unwrap()
+    }
+
+    pub fn scheduler(&self) -> Scheduler {
+        Scheduler::parse(self.get("scheduler")).unwrap()
+    }
+}
+
+/// `config [get <key> | set <key> <value> | unset <key>]`; `config validate` is dispatched
+/// separately by `main`, see `validate`.
+pub fn config(conn: &Connection, config: &Config, args: &[String]) {
+    if args.len() < 3 {
+        for setting in &config.settings {
+            println!("{} = {} ({})", setting.name, setting.value, setting.source);
+        }
+        return;
+    }
+
+    let command = &args[2];
+    match command.as_str() {
+        "get" => {
+            if args.len() < 4 {
+                fail!("Missing <key>");
+                return;
+            }
+            if find_key(&args[3]).is_none() {
+                fail!("Unknown setting: {}", args[3]);
+                return;
+            }
+            println!("{}", config.get(&args[3]));
+        },
+        "set" => {
+            if args.len() < 5 {
+                fail!("Missing <key> <value>");
+                return;
+            }
+            let key = match find_key(&args[3]) {
+                Some(key) => key,
+                None => {
+                    fail!("Unknown setting: {}", args[3]);
+                    return;
+                }
+            };
+            if let Err(e) = (key.validate)(&args[4]) {
+                fail!("Invalid {}: {}", key.name, e);
+                return;
+            }
+            match set_stored_setting(conn, key.name, &args[4]) {
+                Ok(_) => {
+                    println!("{} = {}", key.name, args[4]);
+                },
+                Err(e) => {
+                    fail!("Error saving setting: {}", e);
+                }
+            }
+        },
+        "unset" => {
+            if args.len() < 4 {
+                fail!("Missing <key>");
+                return;
+            }
+            match delete_stored_setting(conn, &args[3]) {
+                Ok(0) => {
+                    println!("{} is not set", args[3]);
+                },
+                Ok(_) => {
+                    println!("Unset {}", args[3]);
+                },
+                Err(e) => {
+                    fail!("Error removing setting: {}", e);
+                }
+            }
+        },
+        _ => {
+            fail!("Unknown config command: {}", command);
+        }
+    }
+}
+
+/// `config validate`: shows every effective setting with its source and
+/// reports invalid values and unknown keys. Returns whether everything is valid.
+pub fn validate(conn: &Connection, args: &mut Vec<String>) -> bool {
+    let stored = match get_stored_settings(conn) {
+        Ok(stored) => stored,
+        Err(e) => {
+            fail!("Error reading settings: {}", e);
+            return false;
+        }
+    };
+
+    let resolved = match resolve(&stored, args) {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            fail!("{}", e);
+            return false;
+        }
+    };
+
+    let mut problems = Vec::new();
+    for (setting, error) in &resolved {
+        println!("{} = {} ({})", setting.name, setting.value, setting.source);
+        if let Some(e) = error {
+            problems.push(format!("invalid {} (from {}): {}", setting.name, setting.source, e));
+        }
+    }
+
+    for (name, _) in &stored {
+        if find_key(name).is_none() {
+            problems.push(format!("unknown key in database: {}", name));
+        }
+    }
+    for (name, _) in std::env::vars() {
+        if name.starts_with("FLASH_") && !KEYS.iter().any(|key| key.env == name) && !OTHER_ENV.contains(&name.as_str()) {
+            problems.push(format!("unknown environment variable: {}", name));
+        }
+    }
+    // Known setting flags were taken out of `args` by `resolve`.
+    for arg in args.iter().skip(3) {
+        if arg.starts_with("--") {
+            problems.push(format!("unknown flag: {}", arg));
+        }
+    }
+
+    if problems.is_empty() {
+        println!("Configuration is valid");
+        return true;
+    }
+
+    for problem in &problems {
+        println!("warning: {}", problem);
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_accepts_the_database_env_var() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::create_tables(&conn).unwrap();
+        std::env::set_var(DB_ENV, "/tmp/flashcards.db");
+        let mut args = vec!["flash".to_string(), "config".to_string(), "validate".to_string()];
+        assert!(validate(&conn, &mut args));
+    }
+}
@@ -0,0 +1,42 @@
+use crossterm::event::{read, Event, KeyCode};
+
+/// How many example rows a destructive command shows before asking.
+pub const EXAMPLE_ROWS: usize = 3;
+
+/// Prints what a destructive command is about to do and asks the user to confirm.
+///
+/// `yes` (from `--yes`) skips the question so scripts can run unattended;
+/// the summary is still printed so the output records what changed.
+pub fn confirm(summary: &str, yes: bool) -> bool {
+    println!("{}", summary);
+
+    if yes {
+        return true;
+    }
+
+    println!("Proceed? (y/n)");
+    loop {
+        if let Event::Key(key_event) = read().unwrap() {
+            match key_event.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => return true,
+                KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => {
+                    println!("Aborted");
+                    return false;
+                },
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Formats a few sample lines for a confirmation summary, e.g. the cards about to change.
+pub fn examples(lines: &[String], total: usize) -> String {
+    let mut out = String::new();
+    for line in lines.iter().take(EXAMPLE_ROWS) {
+        out.push_str(&format!("  {}\n", line));
+    }
+    if total > EXAMPLE_ROWS {
+        out.push_str(&format!("  ... and {} more\n", total - EXAMPLE_ROWS));
+    }
+    out
+}
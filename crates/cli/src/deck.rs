@@ -0,0 +1,426 @@
+// Deck-level commands.
+//
+// A disabled deck is paused as a whole: it's left out of commands that work
+// across all decks, but its cards are untouched and it can still be quizzed
+// by name. Pinned decks are the ones studied daily; they're listed first.
+// A deck's weight biases `quiz all` toward its cards; the default is 1.
+// A deck's direction is the side `quiz` shows first unless told otherwise.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rusqlite::{params, Connection, Result};
+use chrono::NaiveDate;
+use crossterm::style::{Color, Stylize};
+use log::debug;
+
+use crate::args::take_flag;
+use crate::{get_deck_id, suspension_sql, today, DueFilter};
+
+/// Which side of a deck's cards `quiz` shows first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    // Front first: recall the back.
+    Front,
+    // Back first: recall the front.
+    Back,
+    // Alternates with each review of a card, so both directions get practiced
+    // on the one schedule.
+    Alternate,
+    // Picked at random for each card in a session.
+    Random,
+}
+
+impl Direction {
+    pub fn parse(name: &str) -> Option<Direction> {
+        match name {
+            "front" => Some(Direction::Front),
+            "back" => Some(Direction::Back),
+            "alternate" => Some(Direction::Alternate),
+            "random" => Some(Direction::Random),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Direction::Front => "front",
+            Direction::Back => "back",
+            Direction::Alternate => "alternate",
+            Direction::Random => "random",
+        }
+    }
+}
+
+struct DeckSummary {
+    id: i32,
+    name: String,
+    active: bool,
+    pinned: bool,
+    weight: u32,
+    color: Option<String>,
+    direction: String,
+    cards: i32,
+    // Due today, not counting suspended cards, as `quiz` would count them.
+    due: i32,
+}
+
+/// Parses a deck color: a crossterm color name (`red`, `dark_cyan`, ...) or `#rrggbb`.
+pub fn parse_color(color: &str) -> Option<Color> {
+    match color.strip_prefix('#') {
+        Some(hex) if hex.len() == 6 => {
+            let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+            Some(Color::Rgb { r: channel(0)?, g: channel(2)?, b: channel(4)? })
+        },
+        Some(_) => None,
+        None => Color::try_from(color.to_lowercase().as_str()).ok(),
+    }
+}
+
+// Cleared by `--no-color`.
+static COLOR_ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Turns deck colors off for the rest of the process.
+pub fn disable_color() {
+    COLOR_ENABLED.store(false, Ordering::Relaxed);
+}
+
+fn color_enabled() -> bool {
+    // https://no-color.org: any non-empty NO_COLOR turns colors off.
+    COLOR_ENABLED.load(Ordering::Relaxed) && std::env::var("NO_COLOR").map_or(true, |v| v.is_empty())
+}
+
+/// The deck name tinted with its color, or plain when it has none or colors are off.
+pub fn tinted(name: &str, color: Option<&str>) -> String {
+    if !color_enabled() {
+        return name.to_string();
+    }
+    match color.and_then(parse_color) {
+        Some(color) => name.with(color).to_string(),
+        None => name.to_string(),
+    }
+}
+
+/// The deck's name as shown in headers, tinted with its color.
+pub fn deck_label(conn: &Connection, id: i32) -> Result<String> {
+    debug!("deck_label: id={}", id);
+    let mut stmt = conn.prepare("SELECT name, color FROM decks WHERE id = ?1")?;
+    let (name, color): (String, Option<String>) = stmt.query_row(params![id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    Ok(tinted(&name, color.as_deref()))
+}
+
+fn get_decks(conn: &Connection, today: &NaiveDate, include_suspended: bool) -> Result<Vec<DeckSummary>> {
+    debug!("get_decks: today={} include_suspended={}", today, include_suspended);
+    let sql = format!(
+        "SELECT d.id, d.name, d.active, d.pinned, d.weight, d.color, COUNT(f.id),
+            COALESCE(SUM(f.id IS NOT NULL AND {} AND {}), 0), d.default_direction
+        FROM decks d LEFT JOIN flashcards f ON f.deck_id = d.id
+        GROUP BY d.id
+        ORDER BY d.pinned DESC, d.id",
+        DueFilter::Due.sql("?1"),
+        suspension_sql(include_suspended),
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let decks = stmt.query_map(params![today], |row| {
+        Ok(DeckSummary {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            active: row.get(2)?,
+            pinned: row.get(3)?,
+            weight: row.get(4)?,
+            color: row.get(5)?,
+            cards: row.get(6)?,
+            due: row.get(7)?,
+            direction: row.get(8)?,
+        })
+    })?.collect();
+    decks
+}
+
+fn update_deck_active(conn: &Connection, id: i32, active: bool) -> Result<usize> {
+    debug!("update_deck_active: id={} active={}", id, active);
+    conn.execute("UPDATE decks SET active = ?1 WHERE id = ?2", params![active, id])
+}
+
+fn update_deck_pinned(conn: &Connection, id: i32, pinned: bool) -> Result<usize> {
+    debug!("update_deck_pinned: id={} pinned={}", id, pinned);
+    conn.execute("UPDATE decks SET pinned = ?1 WHERE id = ?2", params![pinned, id])
+}
+
+/// Names of the pinned decks, in id order.
+pub fn get_pinned_deck_names(conn: &Connection) -> Result<Vec<String>> {
+    debug!("get_pinned_deck_names");
+    let mut stmt = conn.prepare("SELECT name FROM decks WHERE pinned ORDER BY id")?;
+    let names = stmt.query_map([], |row| row.get(0))?.collect();
+    names
+}
+
+/// Ids of the decks that commands working across all decks include.
+pub fn get_active_deck_ids(conn: &Connection) -> Result<Vec<i32>> {
+    debug!("get_active_deck_ids");
+    let mut stmt = conn.prepare("SELECT id FROM decks WHERE active ORDER BY id")?;
+    let ids = stmt.query_map([], |row| row.get(0))?.collect();
+    ids
+}
+
+pub fn get_deck_weight(conn: &Connection, id: i32) -> Result<u32> {
+    debug!("get_deck_weight: id={}", id);
+    conn.query_row("SELECT weight FROM decks WHERE id = ?1", params![id], |row| row.get(0))
+}
+
+fn update_deck_weight(conn: &Connection, id: i32, weight: u32) -> Result<usize> {
+    debug!("update_deck_weight: id={} weight={}", id, weight);
+    conn.execute("UPDATE decks SET weight = ?1 WHERE id = ?2", params![weight, id])
+}
+
+/// The deck's default direction; see `Direction`.
+pub fn get_deck_direction(conn: &Connection, id: i32) -> Result<Direction> {
+    debug!("get_deck_direction: id={}", id);
+    let name: String = conn.query_row("SELECT default_direction FROM decks WHERE id = ?1", params![id], |row| row.get(0))?;
+    // Only ever written from a parsed Direction.
+    Ok(Direction::parse(&name).unwrap_or(Direction::Front))
+}
+
+fn update_deck_direction(conn: &Connection, id: i32, direction: Direction) -> Result<usize> {
+    debug!("update_deck_direction: id={} direction={}", id, direction.name());
+    conn.execute("UPDATE decks SET default_direction = ?1 WHERE id = ?2", params![direction.name(), id])
+}
+
+fn update_deck_color(conn: &Connection, id: i32, color: Option<&str>) -> Result<usize> {
+    debug!("update_deck_color: id={} color={:?}", id, color);
+    conn.execute("UPDATE decks SET color = ?1 WHERE id = ?2", params![color, id])
+}
+
+/// `list decks`: every deck with its card count and how many cards are due today.
+pub fn list_decks(conn: &Connection) {
+    let today = today();
+    let decks = match get_decks(conn, &today, false) {
+        Ok(decks) => decks,
+        Err(e) => {
+            fail!("Error reading decks: {}", e);
+            return;
+        }
+    };
+
+    println!("  {:>5}  {:<8}  {:>6}  {:<9}  {:>5}  {:>5}  name", "id", "state", "weight", "direction", "cards", "due");
+    for deck in &decks {
+        println!(
+            "{} {:>5}  {:<8}  {:>6}  {:<9}  {:>5}  {:>5}  {}",
+            if deck.pinned { "*" } else { " " },
+            deck.id,
+            if deck.active { "active" } else { "disabled" },
+            deck.weight,
+            deck.direction,
+            deck.cards,
+            deck.due,
+            tinted(&deck.name, deck.color.as_deref()),
+        );
+    }
+}
+
+/// `due [--pinned] [--include-suspended]`: how many cards each active deck
+/// has due today, most first, and the total. Decks with nothing due are left
+/// out, and with `--pinned` so are decks that aren't pinned. Suspended cards
+/// aren't counted unless `--include-suspended` is given, as in `quiz`.
+pub fn due(conn: &Connection, args: &[String]) {
+    let mut args = args.to_vec();
+    let pinned = take_flag(&mut args, "--pinned");
+    let include_suspended = take_flag(&mut args, "--include-suspended");
+
+    let today = today();
+    let mut decks = match get_decks(conn, &today, include_suspended) {
+        Ok(decks) => decks,
+        Err(e) => {
+            fail!("Error reading decks: {}", e);
+            return;
+        }
+    };
+    decks.retain(|deck| deck.active && deck.due > 0 && (deck.pinned || !pinned));
+    // Stable, so decks due the same keep the `list decks` order.
+    decks.sort_by_key(|deck| std::cmp::Reverse(deck.due));
+
+    for deck in &decks {
+        println!("{}: {} due", tinted(&deck.name, deck.color.as_deref()), deck.due);
+    }
+    println!("total: {} due", decks.iter().map(|deck| deck.due).sum::<i32>());
+}
+
+/// `enable <deck_name>` / `disable <deck_name>`
+pub fn set_active(conn: &Connection, args: &[String], active: bool) {
+    if args.len() < 3 {
+        fail!("Missing <deck_name>");
+        return;
+    }
+
+    let deck_id = match get_deck_id(conn, &args[2]) {
+        Ok(id) => id,
+        Err(e) => {
+            fail!("Error getting deck id: {}", e);
+            return;
+        }
+    };
+
+    match update_deck_active(conn, deck_id, active) {
+        Ok(0) => {
+            fail!("No deck with id {}", deck_id);
+        },
+        Ok(_) => {
+            println!("Deck {}: {}", if active { "enabled" } else { "disabled" }, args[2]);
+        },
+        Err(e) => {
+            fail!("Error updating deck: {}", e);
+        }
+    }
+}
+
+/// `pin <deck_name>` / `unpin <deck_name>`
+pub fn set_pinned(conn: &Connection, args: &[String], pinned: bool) {
+    if args.len() < 3 {
+        fail!("Missing <deck_name>");
+        return;
+    }
+
+    let deck_id = match get_deck_id(conn, &args[2]) {
+        Ok(id) => id,
+        Err(e) => {
+            fail!("Error getting deck id: {}", e);
+            return;
+        }
+    };
+
+    match update_deck_pinned(conn, deck_id, pinned) {
+        Ok(0) => {
+            fail!("No deck with id {}", deck_id);
+        },
+        Ok(_) => {
+            println!("Deck {}: {}", if pinned { "pinned" } else { "unpinned" }, args[2]);
+        },
+        Err(e) => {
+            fail!("Error updating deck: {}", e);
+        }
+    }
+}
+
+/// `recolor <deck_name> <color>`; `none` removes the deck's color.
+pub fn recolor(conn: &Connection, args: &[String]) {
+    if args.len() < 4 {
+        fail!("Missing <deck_name> <color>");
+        return;
+    }
+
+    let color = match args[3].as_str() {
+        "none" => None,
+        color if parse_color(color).is_some() => Some(color),
+        color => {
+            fail!("Unknown color: {}", color);
+            return;
+        }
+    };
+
+    let deck_id = match get_deck_id(conn, &args[2]) {
+        Ok(id) => id,
+        Err(e) => {
+            fail!("Error getting deck id: {}", e);
+            return;
+        }
+    };
+
+    match update_deck_color(conn, deck_id, color) {
+        Ok(0) => {
+            fail!("No deck with id {}", deck_id);
+        },
+        Ok(_) => {
+            println!("Deck color set: {}", tinted(&args[2], color));
+        },
+        Err(e) => {
+            fail!("Error updating deck: {}", e);
+        }
+    }
+}
+
+/// `direction <deck_name> <front|back|alternate|random>`: the side `quiz` shows
+/// first for this deck, unless `quiz --direction` says otherwise.
+pub fn set_direction(conn: &Connection, args: &[String]) {
+    if args.len() < 4 {
+        fail!("Missing <deck_name> <direction>");
+        return;
+    }
+
+    let direction = match Direction::parse(&args[3]) {
+        Some(direction) => direction,
+        None => {
+            fail!("Unknown direction: {} (supported: front, back, alternate, random)", args[3]);
+            return;
+        }
+    };
+
+    let deck_id = match get_deck_id(conn, &args[2]) {
+        Ok(id) => id,
+        Err(e) => {
+            fail!("Error getting deck id: {}", e);
+            return;
+        }
+    };
+
+    match update_deck_direction(conn, deck_id, direction) {
+        Ok(0) => {
+            fail!("No deck with id {}", deck_id);
+        },
+        Ok(_) => {
+            println!("Deck direction set: {} {}", args[2], direction.name());
+        },
+        Err(e) => {
+            fail!("Error updating deck: {}", e);
+        }
+    }
+}
+
+/// `reweight <deck_name> <weight>`: how strongly `quiz all` favors the deck.
+pub fn reweight(conn: &Connection, args: &[String]) {
+    if args.len() < 4 {
+        fail!("Missing <deck_name> <weight>");
+        return;
+    }
+
+    let weight: u32 = match args[3].parse() {
+        Ok(weight) if weight >= 1 => weight,
+        _ => {
+            fail!("Weight must be a whole number >= 1, got {}", args[3]);
+            return;
+        }
+    };
+
+    let deck_id = match get_deck_id(conn, &args[2]) {
+        Ok(id) => id,
+        Err(e) => {
+            fail!("Error getting deck id: {}", e);
+            return;
+        }
+    };
+
+    match update_deck_weight(conn, deck_id, weight) {
+        Ok(0) => {
+            fail!("No deck with id {}", deck_id);
+        },
+        Ok(_) => {
+            println!("Deck weight set: {} = {}", args[2], weight);
+        },
+        Err(e) => {
+            fail!("Error updating deck: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_color_accepts_names_and_hex() {
+        assert_eq!(parse_color("red"), Some(Color::Red));
+        assert_eq!(parse_color("Dark_Cyan"), Some(Color::DarkCyan));
+        assert_eq!(parse_color("#ff8000"), Some(Color::Rgb { r: 255, g: 128, b: 0 }));
+        assert_eq!(parse_color("#ff80"), None);
+        assert_eq!(parse_color("#gg0000"), None);
+        assert_eq!(parse_color("mauve"), None);
+    }
+}
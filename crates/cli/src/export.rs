@@ -0,0 +1,200 @@
+use std::fs::write;
+use std::path::Path;
+
+use rusqlite::Connection;
+use log::debug;
+
+use crate::args::{take_flag, take_value};
+use crate::format;
+use crate::parse::escape;
+use crate::{dry_run, get_deck_id, get_deck_name, get_flashcards, json, Flashcard};
+
+// Anki reads the file as HTML, so markup characters are escaped and the
+// separators that would break a row (tabs and newlines) are encoded.
+fn escape_anki_field(text: &str) -> String {
+    text.trim()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\t', "&#9;")
+        .replace("\r\n", "<br>")
+        .replace('\n', "<br>")
+}
+
+/// Tab-separated text that Anki's "Import File" dialog understands. Tags go
+/// in the third column unless anonymized.
+pub fn to_anki(cards: &[Flashcard], anonymize: bool) -> String {
+    let mut out = String::new();
+    out.push_str("#separator:tab\n");
+    out.push_str("#html:true\n");
+    out.push_str("#tags column:3\n");
+
+    for card in cards {
+        out.push_str(&format!(
+            "{}\t{}\t{}\n",
+            escape_anki_field(&card.front),
+            escape_anki_field(&card.back),
+            if anonymize { String::new() } else { card.tags.join(" ") },
+        ));
+    }
+
+    out
+}
+
+/// flash's own card file, as read by `add cards`. Blocks carry their `added:`
+/// date and `tags:` unless anonymized, so `add cards --preserve-added`
+/// restores the date.
+pub fn to_flash(deck_name: &str, cards: &[Flashcard], anonymize: bool) -> String {
+    let mut out = format!("Deck Name: {}\n----\n", deck_name);
+
+    for card in cards {
+        if !anonymize {
+            out.push_str(&format!("added: {}\n", card.added));
+            if !card.tags.is_empty() {
+                out.push_str(&format!("tags: {}\n", card.tags.join(", ")));
+            }
+        }
+        out.push_str(&format!("{}\n<>\n{}\n----\n", escape(card.front.trim()), escape(card.back.trim())));
+    }
+
+    out
+}
+
+/// A deck as a JSON object, read back by the json format:
+///
+///     {
+///       "deck": "Geo",
+///       "cards": [
+///         {"front": "...", "back": "...", "added": "2024-01-10", "next": "2024-01-14", "level": 2, "ease_factor": 2.5, "repetitions": 1, "interval_days": 4, "tags": ["europe"]}
+///       ]
+///     }
+///
+/// Anonymized cards have only `front` and `back`.
+pub fn to_json(deck_name: &str, cards: &[Flashcard], anonymize: bool) -> String {
+    let cards: Vec<String> = cards.iter()
+        .map(|card| {
+            let mut fields = vec![
+                format!("\"front\": {}", json::string(&card.front)),
+                format!("\"back\": {}", json::string(&card.back)),
+            ];
+            if !anonymize {
+                let tags: Vec<String> = card.tags.iter().map(|tag| json::string(tag)).collect();
+                fields.extend([
+                    format!("\"added\": \"{}\"", card.added),
+                    format!("\"next\": \"{}\"", card.next),
+                    format!("\"level\": {}", card.level),
+                    format!("\"ease_factor\": {}", card.ease_factor),
+                    format!("\"repetitions\": {}", card.repetitions),
+                    format!("\"interval_days\": {}", card.interval_days),
+                    format!("\"tags\": [{}]", tags.join(", ")),
+                ]);
+            }
+            format!("    {{{}}}", fields.join(", "))
+        })
+        .collect();
+
+    format!("{{\n  \"deck\": {},\n  \"cards\": [\n{}\n  ]\n}}\n", json::string(deck_name), cards.join(",\n"))
+}
+
+/// `export <deck_name> <path> [--format anki|flash|json] [--anonymize] [--force]`
+///
+/// `--export-format` is the older spelling of `--format`; `formats` lists
+/// the formats that can be exported. Without either, a path ending in `.json`
+/// gets the json format and anything else anki's. json keeps the cards'
+/// review state, so `import` restores the deck as it was.
+///
+/// An existing file at `path` is left alone unless `--force` is given, since
+/// it's often an earlier backup.
+///
+/// `--anonymize` writes only each card's front and back, for sharing a deck.
+/// Added dates, tags, scheduling (level, next and SM-2 state), review counters
+/// and history, and import sources are all left out. Of those, the anki
+/// format only carries tags.
+pub fn export(conn: &Connection, args: &[String]) {
+    let mut args = args.to_vec();
+    let anonymize = take_flag(&mut args, "--anonymize");
+    let force = take_flag(&mut args, "--force");
+
+    let name = match (take_value(&mut args, "--format"), take_value(&mut args, "--export-format")) {
+        (Ok(name), Ok(old_name)) => name.or(old_name),
+        (Err(e), _) | (_, Err(e)) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    if args.len() < 3 {
+        fail!("Missing <deck_name>");
+        return;
+    }
+    if args.len() < 4 {
+        fail!("Missing <path>");
+        return;
+    }
+    let path = &args[3];
+
+    let name = name.unwrap_or_else(|| if path.ends_with(".json") { "json" } else { "anki" }.to_string());
+    let format = match format::find(&name) {
+        Ok(format) if format.can_export() => format,
+        Ok(format) => {
+            fail!("The {} format can't be exported", format.name());
+            return;
+        },
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    let deck_id = match get_deck_id(conn, &args[2]) {
+        Ok(id) => id,
+        Err(e) => {
+            fail!("Error getting deck id: {}", e);
+            return;
+        }
+    };
+
+    let deck_name = match get_deck_name(conn, deck_id) {
+        Ok(name) => name,
+        Err(e) => {
+            fail!("Error reading deck: {}", e);
+            return;
+        }
+    };
+
+    let cards = match get_flashcards(conn, deck_id) {
+        Ok(cards) => cards,
+        Err(e) => {
+            fail!("Error reading flashcards: {}", e);
+            return;
+        }
+    };
+
+    let contents = match format.write(&deck_name, &cards, anonymize) {
+        Ok(contents) => contents,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    if !force && Path::new(path).exists() {
+        fail!("{} already exists; pass --force to overwrite it", path);
+        return;
+    }
+
+    if dry_run() {
+        println!("Would export {} cards to {}", cards.len(), path);
+        return;
+    }
+
+    debug!("export: writing {} cards to {}", cards.len(), path);
+    match write(path, contents) {
+        Ok(_) => {
+            println!("Exported {} cards to {}", cards.len(), path);
+        },
+        Err(e) => {
+            fail!("Error writing file: {}", e);
+        }
+    }
+}
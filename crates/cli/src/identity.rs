@@ -0,0 +1,108 @@
+// Card identity: which existing card counts as the same card, for
+// `add cards --update` / `--skip-duplicates` and `merge --dedup`.
+//
+// By default cards are matched on `front_hash`, a hash of the front in the
+// form typed answers are compared in (see `answer::comparable`), so case,
+// runs of whitespace, Unicode normalization and typographic punctuation
+// don't tell two cards apart. `--match-on front` goes back to matching the
+// exact text.
+//
+// The hash is stored per card, indexed with its deck, and filled in for any
+// card that lacks one whenever the database is opened.
+
+use rusqlite::{params, Connection, Result};
+use log::debug;
+
+use crate::answer;
+use crate::args::take_value;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MatchOn {
+    // The exact front text.
+    Front,
+    // The normalized front, by `front_hash`.
+    Hash,
+}
+
+impl MatchOn {
+    pub fn parse(name: &str) -> Option<MatchOn> {
+        match name {
+            "front" => Some(MatchOn::Front),
+            "hash" => Some(MatchOn::Hash),
+            _ => None,
+        }
+    }
+
+    /// The text `front` is compared by.
+    pub fn key(&self, front: &str) -> String {
+        match self {
+            MatchOn::Front => front.to_string(),
+            MatchOn::Hash => front_hash(front),
+        }
+    }
+}
+
+/// Takes `--match-on front|hash` out of `args`; it defaults to `hash`.
+pub fn take_match_on(args: &mut Vec<String>) -> std::result::Result<MatchOn, String> {
+    match take_value(args, "--match-on")? {
+        None => Ok(MatchOn::Hash),
+        Some(name) => MatchOn::parse(&name).ok_or_else(|| format!("Unknown --match-on: {} (supported: front, hash)", name)),
+    }
+}
+
+/// A 64-bit FNV-1a hash of the normalized front, as 16 hex digits. FNV is used
+/// because, unlike std's hashers, it's guaranteed stable across releases.
+pub fn front_hash(front: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in answer::comparable(front).bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+/// Hashes the front of every card that has no `front_hash` yet.
+pub fn fill_front_hashes(conn: &Connection) -> Result<usize> {
+    let mut stmt = conn.prepare("SELECT id, front FROM flashcards WHERE front_hash IS NULL")?;
+    let cards = stmt.query_map([], |row| Ok((row.get::<_, i32>(0)?, row.get::<_, String>(1)?)))?.collect::<Result<Vec<_>>>()?;
+    if cards.is_empty() {
+        return Ok(0);
+    }
+
+    debug!("fill_front_hashes: {} cards", cards.len());
+    let mut update = conn.prepare("UPDATE flashcards SET front_hash = ?1 WHERE id = ?2")?;
+    for (id, front) in &cards {
+        update.execute(params![front_hash(front), id])?;
+    }
+    Ok(cards.len())
+}
+
+/// The id, front and back of the oldest card in the deck that `front`
+/// matches, or `QueryReturnedNoRows`. Note-type cards are rendered from their fields and
+/// never match.
+pub fn find_card(conn: &Connection, deck_id: i32, front: &str, match_on: MatchOn) -> Result<(i32, String, String)> {
+    debug!("find_card: deck_id={} match_on={:?}", deck_id, match_on);
+    let column = match match_on {
+        MatchOn::Front => "front",
+        MatchOn::Hash => "front_hash",
+    };
+    conn.query_row(
+        &format!("SELECT id, front, back FROM flashcards WHERE deck_id = ?1 AND {} = ?2 AND note_type_id IS NULL ORDER BY id LIMIT 1", column),
+        params![deck_id, match_on.key(front)],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn front_hash_ignores_formatting() {
+        let hash = front_hash("Capital of  France?");
+        assert_eq!(front_hash(" capital of\nfrance? "), hash);
+        assert_eq!(front_hash("Caf\u{e9}"), front_hash("cafe\u{301}"));
+        assert_ne!(front_hash("Capital of Spain?"), hash);
+        assert_eq!(hash.len(), 16);
+    }
+}
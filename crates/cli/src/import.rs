@@ -0,0 +1,626 @@
+// `add cards`: importing card files into their decks, and `add card` for
+// a single card given on the command line.
+
+use std::collections::HashMap;
+use std::fs::read;
+
+use encoding_rs::{Encoding, UTF_8};
+use regex::Regex;
+use rusqlite::Connection;
+use log::debug;
+
+use crate::args::{take_flag, take_value};
+use crate::config::Config;
+use crate::format::{self, Format};
+use crate::identity::{find_card, take_match_on, MatchOn};
+use crate::parse::{check_sides, parse_cards, EmptySides};
+use crate::scheduler::INITIAL_EASE;
+use crate::{deck, get_deck_id_from_name, get_flashcard, insert_deck, insert_flashcard, tags, today, unicode, update_flashcard_text, Deck, Flashcard, Savepoint};
+
+#[derive(Default)]
+struct ImportResult {
+    inserted: usize,
+    updated: usize,
+    // Matched an existing card that `--update` had nothing to change on.
+    unchanged: usize,
+    skipped: usize,
+    errors: usize,
+}
+
+impl ImportResult {
+    fn add(&mut self, other: &ImportResult) {
+        self.inserted += other.inserted;
+        self.updated += other.updated;
+        self.unchanged += other.unchanged;
+        self.skipped += other.skipped;
+        self.errors += other.errors;
+    }
+
+    // Counts that can only be non-zero with `--update` or `--skip-duplicates`
+    // are left out when they're zero.
+    fn summary(&self) -> String {
+        let mut parts = vec![format!("{} added", self.inserted)];
+        for (count, what) in [(self.updated, "updated"), (self.unchanged, "unchanged"), (self.skipped, "skipped")] {
+            if count > 0 {
+                parts.push(format!("{} {}", count, what));
+            }
+        }
+        parts.push(format!("{} errors", self.errors));
+        parts.join(", ")
+    }
+}
+
+/// What to do with a card that matches one already in its deck; see `identity`.
+#[derive(Clone, Copy, PartialEq)]
+enum OnDuplicate {
+    Insert,
+    Skip,
+    // Take the file's front and back, and its tags by `TagMode`.
+    Update,
+}
+
+/// How `--update` treats the tags of a card it updates.
+#[derive(Clone, Copy, PartialEq)]
+enum TagMode {
+    // Add the file's tags to the card's.
+    Append,
+    // The card ends up with exactly the file's tags, none if it has none.
+    Replace,
+}
+
+struct ImportOptions<'a> {
+    preserve_added: bool,
+    source: Option<&'a str>,
+    // Any malformed card fails the whole import; see `add_cards`.
+    strict: bool,
+    empty_sides: EmptySides,
+    // Store front and back as NFC; see `unicode`.
+    normalize_unicode: bool,
+    format: &'a dyn Format,
+    // What the files are encoded in when they don't start with a BOM.
+    encoding: Option<&'static Encoding>,
+    on_duplicate: OnDuplicate,
+    tag_mode: TagMode,
+    // Show each updated card's tag changes.
+    verbose: bool,
+    match_on: MatchOn,
+    // Add the deck a file names if there's none by that name; see `import`.
+    create_deck: bool,
+    // Picks each card's deck from its front; see `add_cards`.
+    deck_from_prefix: Option<&'a Regex>,
+}
+
+// The id of the deck called `name`, added first if it's missing and `create`
+// is set.
+fn deck_id_for(conn: &Connection, name: &str, create: bool) -> Result<i32, String> {
+    match get_deck_id_from_name(conn, name) {
+        Ok(id) => Ok(id),
+        Err(rusqlite::Error::QueryReturnedNoRows) if create => {
+            let deck = Deck { name: name.to_string(), color: None, direction: deck::Direction::Front };
+            insert_deck(conn, &deck).map_err(|e| format!("Error adding deck: {}", e))?;
+            let id = conn.last_insert_rowid() as i32;
+            println!("Deck added: {} (id {})", name, id);
+            Ok(id)
+        },
+        Err(e) => Err(format!("Error getting deck id: {}", e)),
+    }
+}
+
+/// Splits a deck name off `front` with `prefix`: its first capture group is
+/// the name, and the whole match is cut from the front. `None` if it doesn't
+/// match or captures an empty name.
+fn split_deck_prefix(prefix: &Regex, front: &str) -> Option<(String, String)> {
+    let captures = prefix.captures(front)?;
+    let name = captures.get(1)?.as_str().trim();
+    if name.is_empty() {
+        return None;
+    }
+    let matched = captures.get(0)?;
+    let rest = format!("{}{}", &front[..matched.start()], &front[matched.end()..]);
+    Some((name.to_string(), rest.trim().to_string()))
+}
+
+// Reads a card file as text, transcoding it to UTF-8. A byte order mark
+// decides the encoding by itself; otherwise it's `encoding`, or UTF-8.
+fn read_text(path: &str, encoding: Option<&'static Encoding>) -> Result<String, String> {
+    let bytes = read(path).map_err(|e| format!("Error reading file: {}", e))?;
+    let (text, used, had_errors) = encoding.unwrap_or(UTF_8).decode(&bytes);
+    debug!("read_text: {} decoded as {}", path, used.name());
+
+    if had_errors {
+        return Err(match encoding {
+            Some(_) => format!("Error reading file: not valid {}", used.name()),
+            None => "Error reading file: not valid UTF-8; if it's saved in another encoding, pass it with --encoding (e.g. latin1, utf-16le, shift_jis)".to_string(),
+        });
+    }
+    Ok(text.into_owned())
+}
+
+fn import_file(conn: &Connection, path: &str, options: &ImportOptions) -> Result<ImportResult, String> {
+    let text = read_text(path, options.encoding)?;
+    // Cards remember the file they came from unless tagged with `--source`.
+    import_text(conn, &text, options.source.unwrap_or(path), options)
+}
+
+// Imports the cards of one file's `text`, tagging them with `source`.
+fn import_text(conn: &Connection, text: &str, source: &str, options: &ImportOptions) -> Result<ImportResult, String> {
+    let file = options.format.parse(text)?;
+    let name = file.deck_name.as_str();
+
+    let deck_id = deck_id_for(conn, name, options.create_deck)?;
+    debug!("import_text: {} -> deck {} ({})", source, name, deck_id);
+
+    let added_date = today();
+
+    let parsed = parse_cards(deck_id, file.cards, &added_date, options.preserve_added, source, options.empty_sides);
+    let (mut cards, parse_errors) = (parsed.cards, parsed.errors);
+
+    if options.normalize_unicode {
+        for card in &mut cards {
+            card.front = unicode::nfc(&card.front);
+            card.back = unicode::nfc(&card.back);
+        }
+    }
+
+    for warning in &parsed.warnings {
+        println!("Warning: card {}: {}", warning.block, warning.message);
+    }
+
+    let mut result = ImportResult { errors: parse_errors.len(), ..Default::default() };
+    for error in &parse_errors {
+        if options.strict {
+            fail!("Error in card {}: {}", error.block, error.message);
+        } else {
+            println!("Skipping card {}: {}", error.block, error.message);
+        }
+    }
+
+    // The import is going to be rolled back; don't report cards as added.
+    if options.strict && !parse_errors.is_empty() {
+        return Ok(result);
+    }
+
+    let mut prefix_decks: HashMap<String, i32> = HashMap::new();
+    for card in &mut cards {
+        if let Some((name, front)) = options.deck_from_prefix.and_then(|prefix| split_deck_prefix(prefix, &card.front)) {
+            if front.is_empty() {
+                println!("Skipping card: nothing left of the front after its deck prefix: {}", card.front);
+                result.errors += 1;
+                continue;
+            }
+            card.deck_id = match prefix_decks.get(&name) {
+                Some(id) => *id,
+                None => match deck_id_for(conn, &name, true) {
+                    Ok(id) => *prefix_decks.entry(name).or_insert(id),
+                    Err(e) => {
+                        println!("{}", e);
+                        result.errors += 1;
+                        continue;
+                    }
+                },
+            };
+            card.front = front;
+        }
+
+        if options.on_duplicate != OnDuplicate::Insert {
+            match find_card(conn, card.deck_id, &card.front, options.match_on) {
+                Ok((id, front, back)) => {
+                    if options.on_duplicate == OnDuplicate::Skip {
+                        println!("Skipping duplicate of card {}: {}", id, card.front);
+                        result.skipped += 1;
+                    } else if let Err(e) = update_card(conn, id, &front, &back, card, options, &mut result) {
+                        fail!("Error updating flashcard: {}", e);
+                        result.errors += 1;
+                    }
+                    continue;
+                },
+                Err(rusqlite::Error::QueryReturnedNoRows) => {},
+                Err(e) => {
+                    fail!("Error looking up flashcard: {}", e);
+                    result.errors += 1;
+                    continue;
+                }
+            }
+        }
+
+        match insert_flashcard(conn, card) {
+            Ok(_) => {
+                println!("Flashcard added: {}", card.front);
+                let row_id = conn.last_insert_rowid();
+                card.id = row_id as i32;
+                if let Err(e) = tags::insert_tags(conn, card.id, &card.tags) {
+                    fail!("Error tagging flashcard: {}", e);
+                    result.errors += 1;
+                    continue;
+                }
+                result.inserted += 1;
+            },
+            Err(e) => {
+                fail!("Error adding flashcard: {}", e);
+                result.errors += 1;
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+// `--update`: card `id`, with `front` and `back`, matched the file's `card`.
+fn update_card(conn: &Connection, id: i32, front: &str, back: &str, card: &Flashcard, options: &ImportOptions, result: &mut ImportResult) -> rusqlite::Result<()> {
+    let changed = front != card.front || back != card.back;
+    if changed {
+        update_flashcard_text(conn, id, &card.front, &card.back)?;
+    }
+
+    let old_tags = get_flashcard(conn, id)?.tags;
+    let new_tags = updated_tags(&old_tags, &card.tags, options.tag_mode);
+    let retagged = new_tags != old_tags;
+    if retagged {
+        tags::set_tags(conn, id, &new_tags)?;
+    }
+
+    if changed || retagged {
+        println!("Flashcard updated: {} (id {})", card.front, id);
+        if options.verbose && retagged {
+            println!("  tags: {} -> {}", tag_list(&old_tags), tag_list(&new_tags));
+        }
+        result.updated += 1;
+    } else {
+        result.unchanged += 1;
+    }
+    Ok(())
+}
+
+// The tags a card has after `--update` with the file's `tags`, sorted.
+fn updated_tags(old: &[String], tags: &[String], mode: TagMode) -> Vec<String> {
+    match mode {
+        TagMode::Append => {
+            let mut tags = [old, tags].concat();
+            tags.sort();
+            tags.dedup();
+            tags
+        },
+        TagMode::Replace => tags.to_vec(),
+    }
+}
+
+fn tag_list(tags: &[String]) -> String {
+    if tags.is_empty() {
+        "(none)".to_string()
+    } else {
+        tags.join(", ")
+    }
+}
+
+/// `add cards <file>... [--format flash] [--encoding <label>] [--preserve-added] [--source <tag>] [--strict] [--normalize-unicode] [--update [--append-tags | --replace-tags] | --skip-duplicates] [--match-on front|hash] [--deck-from-front-prefix <regex>] [--verbose]`
+///
+/// `formats` lists the formats that can be imported. `--encoding` takes any
+/// WHATWG encoding label, such as `latin1` or `utf-16le`.
+///
+/// A card that matches one already in its deck (see `identity`; `--match-on`
+/// picks how) is added again by default. `--skip-duplicates` leaves it out;
+/// `--update` writes its front and back over the existing card, keeping the
+/// card's schedule and history. Its tags are added to the card's
+/// (`--append-tags`, the default), or with `--replace-tags` become the card's
+/// only tags, so a card whose block has no `tags:` line loses all of them.
+/// `--verbose` shows the tags of each card they change.
+///
+/// `--deck-from-front-prefix` routes cards to decks by their front: where the
+/// regex matches, its first capture group names the card's deck, which is
+/// added if it doesn't exist yet, and the match is cut from the stored front.
+/// With `'^\[(\w+)\]\s*'`, `[verb] to run` goes to the `verb` deck as
+/// `to run`. Cards it doesn't match stay in the file's deck.
+///
+/// All files are imported in one transaction; each file's header picks its
+/// own deck, so a single command can fill several decks.
+///
+/// With `--strict`, any card that fails to parse or insert rolls the whole
+/// transaction back and the process exits with status 1, so card files can be
+/// checked in CI.
+pub fn add_cards(conn: &Connection, config: &Config, args: &[String]) {
+    let mut args = args.to_vec();
+    // Keep the `added:` dates carried by the card file instead of stamping today.
+    let preserve_added = take_flag(&mut args, "--preserve-added");
+    let strict = take_flag(&mut args, "--strict");
+    let normalize_unicode = take_flag(&mut args, "--normalize-unicode");
+    let on_duplicate = match (take_flag(&mut args, "--update"), take_flag(&mut args, "--skip-duplicates")) {
+        (true, true) => {
+            fail!("--update and --skip-duplicates can't be used together");
+            return;
+        },
+        (true, false) => OnDuplicate::Update,
+        (false, true) => OnDuplicate::Skip,
+        (false, false) => OnDuplicate::Insert,
+    };
+    let tag_mode = match (take_flag(&mut args, "--append-tags"), take_flag(&mut args, "--replace-tags")) {
+        (true, true) => {
+            fail!("--append-tags and --replace-tags can't be used together");
+            return;
+        },
+        (append, replace) if (append || replace) && on_duplicate != OnDuplicate::Update => {
+            fail!("--append-tags and --replace-tags only apply with --update");
+            return;
+        },
+        (_, true) => TagMode::Replace,
+        (_, false) => TagMode::Append,
+    };
+    let verbose = take_flag(&mut args, "--verbose");
+    let match_on = match take_match_on(&mut args) {
+        Ok(match_on) => match_on,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+    let deck_from_prefix = match take_value(&mut args, "--deck-from-front-prefix") {
+        Ok(None) => None,
+        Ok(Some(pattern)) => match Regex::new(&pattern) {
+            Ok(prefix) if prefix.captures_len() > 1 => Some(prefix),
+            Ok(_) => {
+                fail!("--deck-from-front-prefix needs a capture group for the deck name, as in '^\\[(\\w+)\\]'");
+                return;
+            },
+            Err(e) => {
+                fail!("Invalid --deck-from-front-prefix: {}", e);
+                return;
+            }
+        },
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+    let source = match take_value(&mut args, "--source") {
+        Ok(source) => source,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+    let format = match take_value(&mut args, "--format").map(|name| format::find(name.as_deref().unwrap_or("flash"))) {
+        Ok(Ok(format)) if format.can_import() => format,
+        Ok(Ok(format)) => {
+            fail!("The {} format can't be imported", format.name());
+            return;
+        },
+        Ok(Err(e)) | Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    let encoding = match take_value(&mut args, "--encoding") {
+        Ok(None) => None,
+        Ok(Some(label)) => match Encoding::for_label(label.as_bytes()) {
+            Some(encoding) => Some(encoding),
+            None => {
+                fail!("Unknown encoding: {}", label);
+                return;
+            }
+        },
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    if args.len() < 4 {
+        fail!("Missing <file>");
+        return;
+    }
+
+    let options = ImportOptions {
+        preserve_added,
+        source: source.as_deref(),
+        strict,
+        empty_sides: config.empty_sides(),
+        normalize_unicode,
+        format,
+        encoding,
+        on_duplicate,
+        tag_mode,
+        verbose,
+        match_on,
+        create_deck: false,
+        deck_from_prefix: deck_from_prefix.as_ref(),
+    };
+    import_files(conn, &args[3..], &options);
+}
+
+/// `import <file>... [--strict]`: restores decks from json files, as written
+/// by `export --format json`.
+///
+/// A deck that doesn't exist yet is created. Into one that does, only new
+/// cards are merged: a card matching one already in the deck (see `identity`)
+/// is skipped, so importing the same backup twice adds nothing. Either way,
+/// cards keep their added dates, tags and review state.
+pub fn import(conn: &Connection, config: &Config, args: &[String]) {
+    let mut args = args.to_vec();
+    let strict = take_flag(&mut args, "--strict");
+
+    if args.len() < 3 {
+        fail!("Missing <file>");
+        return;
+    }
+
+    let options = ImportOptions {
+        preserve_added: true,
+        source: None,
+        strict,
+        empty_sides: config.empty_sides(),
+        normalize_unicode: false,
+        format: format::find("json").unwrap(),
+        encoding: None,
+        on_duplicate: OnDuplicate::Skip,
+        tag_mode: TagMode::Append,
+        verbose: false,
+        match_on: MatchOn::Hash,
+        create_deck: true,
+        deck_from_prefix: None,
+    };
+    import_files(conn, &args[2..], &options);
+}
+
+// Imports `paths` in one transaction, reporting on each file; see `add_cards`.
+fn import_files(conn: &Connection, paths: &[String], options: &ImportOptions) {
+    let tx = match Savepoint::new(conn, "add_cards") {
+        Ok(tx) => tx,
+        Err(e) => {
+            fail!("Error starting import: {}", e);
+            return;
+        }
+    };
+
+    let mut total = ImportResult::default();
+    for path in paths {
+        match import_file(&tx, path, options) {
+            Ok(result) => {
+                println!("{}: {}", path, result.summary());
+                total.add(&result);
+            },
+            Err(e) => {
+                fail!("{}: {}", path, e);
+                total.errors += 1;
+            }
+        }
+    }
+
+    if options.strict && total.errors > 0 {
+        if let Err(e) = tx.rollback() {
+            fail!("Error rolling back import: {}", e);
+        }
+        fail!("Import failed with {} errors; no cards were added", total.errors);
+        return;
+    }
+
+    if let Err(e) = tx.commit() {
+        fail!("Error saving import: {}", e);
+        return;
+    }
+
+    if paths.len() > 1 {
+        println!("Total: {}", total.summary());
+    }
+}
+
+/// Adds the starter deck `text` for `init --from`, creating its deck, in one
+/// transaction. Cards it already has are skipped, so seeding twice adds
+/// nothing. Its cards are tagged with the source `starter:<name>`.
+pub fn import_starter(conn: &Connection, config: &Config, name: &str, text: &str) {
+    let options = ImportOptions {
+        preserve_added: false,
+        source: None,
+        strict: true,
+        empty_sides: config.empty_sides(),
+        normalize_unicode: false,
+        format: format::find("flash").unwrap(),
+        encoding: None,
+        on_duplicate: OnDuplicate::Skip,
+        tag_mode: TagMode::Append,
+        verbose: false,
+        match_on: MatchOn::Hash,
+        create_deck: true,
+        deck_from_prefix: None,
+    };
+
+    let tx = match Savepoint::new(conn, "import_starter") {
+        Ok(tx) => tx,
+        Err(e) => {
+            fail!("Error starting import: {}", e);
+            return;
+        }
+    };
+    let result = match import_text(&tx, text, &format!("starter:{}", name), &options) {
+        Ok(result) if result.errors == 0 => result,
+        Ok(result) => {
+            fail!("Starter deck {} has {} broken cards", name, result.errors);
+            return;
+        },
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+    if let Err(e) = tx.commit() {
+        fail!("Error saving import: {}", e);
+        return;
+    }
+    println!("{}: {}", name, result.summary());
+}
+
+/// `add card <deck_name> <front> <back>`: adds one card, set up the same way
+/// as an imported one.
+pub fn add_card(conn: &Connection, config: &Config, args: &[String]) {
+    if args.len() < 6 {
+        fail!("Usage: add card <deck_name> <front> <back>");
+        return;
+    }
+
+    let deck_id = match get_deck_id_from_name(conn, &args[3]) {
+        Ok(id) => id,
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            fail!("No deck named {}", args[3]);
+            return;
+        },
+        Err(e) => {
+            fail!("Error getting deck id: {}", e);
+            return;
+        }
+    };
+
+    let (front, back) = (&args[4], &args[5]);
+    if let Some(problem) = check_sides(front, back) {
+        match config.empty_sides() {
+            EmptySides::Reject => {
+                fail!("Can't add card: {}", problem);
+                return;
+            },
+            EmptySides::Warn => println!("Warning: {}", problem),
+        }
+    }
+
+    let today = today();
+    let card = Flashcard {
+        id: -1, // dummy value
+        deck_id,
+        front: front.to_string(),
+        back: back.to_string(),
+        added: today,
+        next: today,
+        level: 1,
+        ease_factor: INITIAL_EASE,
+        repetitions: 0,
+        interval_days: 0,
+        note_type_id: None,
+        source: None,
+        suspended: false,
+        tags: Vec::new(),
+    };
+
+    match insert_flashcard(conn, &card) {
+        Ok(_) => println!("Flashcard added: {} (id {})", card.front, conn.last_insert_rowid()),
+        Err(e) => fail!("Error adding flashcard: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deck_prefixes_are_cut_from_the_front() {
+        let prefix = Regex::new(r"^\[(\w*)\]\s*").unwrap();
+        assert_eq!(split_deck_prefix(&prefix, "[verb]  to run"), Some(("verb".to_string(), "to run".to_string())));
+        assert_eq!(split_deck_prefix(&prefix, "[] to run"), None);
+        assert_eq!(split_deck_prefix(&prefix, "to [verb] run"), None);
+    }
+
+    #[test]
+    fn updates_append_or_replace_tags() {
+        let tags = |list: &[&str]| list.iter().map(|tag| tag.to_string()).collect::<Vec<_>>();
+        let old = tags(&["europe", "verb"]);
+        assert_eq!(updated_tags(&old, &tags(&["asia", "verb"]), TagMode::Append), tags(&["asia", "europe", "verb"]));
+        assert_eq!(updated_tags(&old, &tags(&["asia", "verb"]), TagMode::Replace), tags(&["asia", "verb"]));
+        assert!(updated_tags(&old, &[], TagMode::Replace).is_empty());
+    }
+}
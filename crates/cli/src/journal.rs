@@ -0,0 +1,61 @@
+// Opt-in journaling of quiz sessions (`quiz --journal`).
+//
+// Grades are written as soon as each card is answered, so a crash never loses
+// scheduling; what it loses is the session itself: the shuffled queue and the
+// options it was started with. The journal keeps both until the session ends
+// cleanly, so `quiz --resume-from-crash` can pick up where it stopped.
+
+use rusqlite::{params, Connection, Result};
+use log::debug;
+
+use crate::{get_meta, set_meta, Savepoint};
+
+/// An interrupted session: the arguments it was started with and the cards
+/// still to go, in order.
+pub struct Pending {
+    pub args: Vec<String>,
+    pub card_ids: Vec<i32>,
+}
+
+/// Starts a journal for a session over `card_ids`, replacing any earlier one.
+pub fn save(conn: &Connection, args: &[String], card_ids: &[i32]) -> Result<()> {
+    debug!("journal::save: {} cards", card_ids.len());
+    let tx = Savepoint::new(conn, "journal")?;
+    tx.execute("DELETE FROM journal", [])?;
+    for (position, id) in card_ids.iter().enumerate() {
+        tx.execute("INSERT INTO journal (position, card_id) VALUES (?1, ?2)", params![position as i32, id])?;
+    }
+    // Arguments can contain spaces but never newlines.
+    set_meta(&tx, "journal_args", &args.join("\n"))?;
+    tx.commit()
+}
+
+/// The interrupted session, if there is one.
+pub fn pending(conn: &Connection) -> Result<Option<Pending>> {
+    let args = match get_meta(conn, "journal_args")? {
+        Some(args) => args.split('\n').map(str::to_string).collect(),
+        None => return Ok(None),
+    };
+
+    let mut stmt = conn.prepare("SELECT card_id FROM journal ORDER BY position")?;
+    let card_ids = stmt.query_map([], |row| row.get(0))?.collect::<Result<Vec<i32>>>()?;
+    if card_ids.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Pending { args, card_ids }))
+}
+
+/// Takes an answered card off the journal.
+pub fn mark_done(conn: &Connection, card_id: i32) -> Result<()> {
+    debug!("journal::mark_done: card_id={}", card_id);
+    conn.execute("DELETE FROM journal WHERE card_id = ?1", params![card_id])?;
+    Ok(())
+}
+
+/// Removes the journal once its session has ended cleanly.
+pub fn clear(conn: &Connection) -> Result<()> {
+    debug!("journal::clear");
+    conn.execute("DELETE FROM journal", [])?;
+    conn.execute("DELETE FROM meta WHERE key = 'journal_args'", [])?;
+    Ok(())
+}
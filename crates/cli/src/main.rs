@@ -1,14 +1,53 @@
-use std::fs::read_to_string;
-use std::time::Duration;
+// Prints an error the way every command reports one, on stdout, and marks
+// the run as failed so flash exits with status 1.
+macro_rules! fail {
+    ($($arg:tt)*) => {{
+        println!($($arg)*);
+        crate::set_failed();
+    }};
+}
+
+mod answer;
+mod args;
+mod compare;
+mod config;
+mod confirm;
+mod deck;
+mod export;
+mod format;
+mod identity;
+mod import;
+mod journal;
+mod json;
+mod manage;
+mod order;
+mod parse;
+mod plan;
+mod report;
+mod quiz;
+mod scheduler;
+mod session_report;
+mod starter;
+mod tags;
+mod template;
+mod typos;
+mod unicode;
+
+use std::path::PathBuf;
+use std::process::ExitCode;
 
-use rusqlite::{params, Connection, Result};
-use chrono::{NaiveDate, Local, Days};
-use crossterm::event::{read, poll, Event, KeyCode};
-use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rusqlite::{params, Connection, OpenFlags, Result};
+use chrono::{Local, NaiveDate, NaiveDateTime};
+use log::{debug, trace, warn};
+
+use args::{take_flag, take_value};
+use scheduler::{Grade, Review};
 
 struct Deck {
     name: String,
+    // A crossterm color name or `#rrggbb`, used to tint the deck's name.
+    color: Option<String>,
+    direction: deck::Direction,
 }
 
 struct Flashcard {
@@ -19,320 +58,1028 @@ struct Flashcard {
     added: NaiveDate,
     next: NaiveDate,
     level: i32,
+    // SM-2 state; see `scheduler::CardState`.
+    ease_factor: f64,
+    repetitions: i32,
+    interval_days: i32,
+    // Set for cards rendered from a note type; see `template`.
+    note_type_id: Option<i32>,
+    // Where the card was imported from: a file path or a `--source` tag.
+    source: Option<String>,
+    // Suspended cards are kept but left out of quizzes and counts.
+    suspended: bool,
+    // Sorted; see `tags`.
+    tags: Vec<String>,
+}
+
+struct Session {
+    started_at: NaiveDateTime,
+    ended_at: NaiveDateTime,
+    // Names of the quizzed decks, comma separated.
+    decks: String,
+    cards_reviewed: i32,
+    correct: i32,
 }
 
 struct FlashcardLog {
     question_id: i32,
-    answer: bool,
+    grade: Grade,
+    reviewed_at: NaiveDateTime,
 }
 
 fn create_table(conn: &Connection, sql: &str) -> Result<()> {
+    trace!("{}", sql);
     conn.execute(sql, [])?;
     Ok(())
 }
 
+// The current definition of `flashcards`, created as `name`; see
+// `add_foreign_keys`.
+fn flashcards_table(name: &str) -> String {
+    format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+            id INTEGER PRIMARY KEY,
+            deck_id INTEGER REFERENCES decks (id),
+            front TEXT,
+            back TEXT,
+            added DATE,
+            next DATE,
+            level INTEGER,
+            note_type_id INTEGER,
+            source TEXT,
+            review_count INTEGER NOT NULL DEFAULT 0,
+            lapse_count INTEGER NOT NULL DEFAULT 0,
+            suspended BOOLEAN NOT NULL DEFAULT 0,
+            order_index INTEGER NOT NULL DEFAULT 0,
+            ease_factor REAL NOT NULL DEFAULT 2.5,
+            repetitions INTEGER NOT NULL DEFAULT 0,
+            interval_days INTEGER NOT NULL DEFAULT 0,
+            front_hash TEXT
+        )",
+        name,
+    )
+}
+
+const FLASHCARDS_COLUMNS: &str = "id, deck_id, front, back, added, next, level, note_type_id, source, review_count, lapse_count,
+    suspended, order_index, ease_factor, repetitions, interval_days, front_hash";
+
+fn flashcard_log_table(name: &str) -> String {
+    format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+            question_id INTEGER REFERENCES flashcards (id),
+            answer BOOLEAN,
+            reviewed_at DATETIME,
+            grade TEXT
+        )",
+        name,
+    )
+}
+
 fn create_tables(conn: &Connection) -> Result<()> {
     create_table(
         conn,
         "CREATE TABLE IF NOT EXISTS decks (
             id INTEGER PRIMARY KEY,
-            name TEXT
+            name TEXT,
+            active BOOLEAN NOT NULL DEFAULT 1,
+            pinned BOOLEAN NOT NULL DEFAULT 0,
+            weight INTEGER NOT NULL DEFAULT 1,
+            color TEXT,
+            default_direction TEXT NOT NULL DEFAULT 'front'
         )"
     )?;
 
+    create_table(conn, &flashcards_table("flashcards"))?;
+
+    create_table(conn, &flashcard_log_table("flashcard_log"))?;
+
     create_table(
         conn,
-        "CREATE TABLE IF NOT EXISTS flashcards (
+        "CREATE TABLE IF NOT EXISTS note_types (
             id INTEGER PRIMARY KEY,
-            deck_id INTEGER,
-            front TEXT,
-            back TEXT,
-            added DATE,
-            next DATE,
-            level INTEGER
+            name TEXT UNIQUE,
+            fields TEXT,
+            front_template TEXT,
+            back_template TEXT
+        )"
+    )?;
+
+    create_table(
+        conn,
+        "CREATE TABLE IF NOT EXISTS card_fields (
+            card_id INTEGER,
+            name TEXT,
+            value TEXT
+        )"
+    )?;
+
+    create_table(
+        conn,
+        "CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT
+        )"
+    )?;
+
+    // One row per finished quiz session.
+    create_table(
+        conn,
+        "CREATE TABLE IF NOT EXISTS sessions (
+            id INTEGER PRIMARY KEY,
+            started_at DATETIME,
+            ended_at DATETIME,
+            decks TEXT,
+            cards_reviewed INTEGER,
+            correct INTEGER
         )"
     )?;
 
+    // The queue of a journaled quiz session; see `journal`.
     create_table(
         conn,
-        "CREATE TABLE IF NOT EXISTS flashcard_log (
-            question_id INTEGER,
-            answer BOOLEAN
+        "CREATE TABLE IF NOT EXISTS journal (
+            position INTEGER PRIMARY KEY,
+            card_id INTEGER
+        )"
+    )?;
+
+    // Wrong typed answers; see `typos`.
+    create_table(
+        conn,
+        "CREATE TABLE IF NOT EXISTS typos (
+            card_id INTEGER,
+            answer TEXT,
+            captured_at DATETIME
+        )"
+    )?;
+
+    // One row per tag on a card; see `tags`.
+    create_table(
+        conn,
+        "CREATE TABLE IF NOT EXISTS card_tags (
+            card_id INTEGER,
+            tag TEXT,
+            UNIQUE (card_id, tag)
+        )"
+    )?;
+
+    // Changes made to a card by hand rather than by reviewing it, such as
+    // `promote`; `history` lists them along with the reviews.
+    create_table(
+        conn,
+        "CREATE TABLE IF NOT EXISTS card_events (
+            card_id INTEGER,
+            event TEXT,
+            detail TEXT,
+            at DATETIME
+        )"
+    )?;
+
+    // How many of `MIGRATIONS` have been applied; a single row.
+    create_table(
+        conn,
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER NOT NULL
+        )"
+    )?;
+
+    // Internal state that isn't a user setting.
+    create_table(
+        conn,
+        "CREATE TABLE IF NOT EXISTS meta (
+            key TEXT PRIMARY KEY,
+            value TEXT
         )"
     )?;
 
     Ok(())
 }
 
+fn table_exists(conn: &Connection, table: &str) -> Result<bool> {
+    let mut stmt = conn.prepare("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1")?;
+    let count: i32 = stmt.query_row(params![table], |row| row.get(0))?;
+    Ok(count > 0)
+}
+
+/// Adds `column` to `table` unless it's already there; returns whether it was added.
+fn add_column(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt.query_map([], |row| row.get::<_, String>(1))?.collect::<Result<Vec<_>>>()?;
+
+    if columns.iter().any(|c| c == column) {
+        return Ok(false);
+    }
+
+    let sql = format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition);
+    debug!("{}", sql);
+    conn.execute(&sql, [])?;
+    Ok(true)
+}
+
+/// Recomputes the per-card review counters from `flashcard_log`, which stays
+/// the source of truth for them.
+fn rebuild_counters(conn: &Connection) -> Result<usize> {
+    debug!("rebuild_counters");
+    conn.execute(
+        "UPDATE flashcards SET
+            review_count = (SELECT COUNT(*) FROM flashcard_log WHERE question_id = flashcards.id),
+            lapse_count = (SELECT COUNT(*) FROM flashcard_log WHERE question_id = flashcards.id AND NOT answer)",
+        [],
+    )
+}
+
+// A step of `migrate`. Steps must be safe to run on a database that already
+// has what they add, since `init` creates the current schema directly.
+type Migration = fn(&Connection) -> Result<()>;
+
+// In order; new steps go at the end.
+const MIGRATIONS: &[Migration] = &[add_columns, add_foreign_keys, add_review_grades, rename_both_direction];
+
+fn schema_version(conn: &Connection) -> Result<usize> {
+    let mut stmt = conn.prepare("SELECT version FROM schema_version")?;
+    match stmt.query_row([], |row| row.get::<_, i64>(0)) {
+        Ok(version) => Ok(version as usize),
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            conn.execute("INSERT INTO schema_version (version) VALUES (0)", [])?;
+            Ok(0)
+        },
+        Err(e) => Err(e),
+    }
+}
+
+/// Brings databases created by older versions up to the current schema.
+///
+/// Runs the steps in `MIGRATIONS` that `schema_version` says are pending,
+/// each in its own savepoint along with the version bump. Foreign keys are
+/// only turned on afterwards, so tables can be rebuilt.
+fn migrate(conn: &Connection) -> Result<()> {
+    // Databases that haven't been initialized yet are left for `init`.
+    if !table_exists(conn, "decks")? {
+        return Ok(());
+    }
+
+    // Creates any tables added since the database was initialized.
+    create_tables(conn)?;
+
+    let version = schema_version(conn)?;
+    for (i, step) in MIGRATIONS.iter().enumerate().skip(version) {
+        debug!("migrate: step {} of {}", i + 1, MIGRATIONS.len());
+        let tx = Savepoint::new(conn, "migrate")?;
+        step(&tx)?;
+        tx.execute("UPDATE schema_version SET version = ?1", params![(i + 1) as i64])?;
+        tx.commit()?;
+    }
+
+    // Not a step: swap leaves new fronts to be hashed here.
+    identity::fill_front_hashes(conn)?;
+    Ok(())
+}
+
+// The columns added one at a time before there were schema versions.
+fn add_columns(conn: &Connection) -> Result<()> {
+    add_column(conn, "decks", "active", "BOOLEAN NOT NULL DEFAULT 1")?;
+    add_column(conn, "decks", "color", "TEXT")?;
+    add_column(conn, "decks", "pinned", "BOOLEAN NOT NULL DEFAULT 0")?;
+    add_column(conn, "decks", "weight", "INTEGER NOT NULL DEFAULT 1")?;
+    add_column(conn, "decks", "default_direction", "TEXT NOT NULL DEFAULT 'front'")?;
+    add_column(conn, "flashcards", "note_type_id", "INTEGER")?;
+    add_column(conn, "flashcards", "source", "TEXT")?;
+    // Reviews logged before this column existed keep a NULL timestamp.
+    add_column(conn, "flashcard_log", "reviewed_at", "DATETIME")?;
+
+    let added_review_count = add_column(conn, "flashcards", "review_count", "INTEGER NOT NULL DEFAULT 0")?;
+    let added_lapse_count = add_column(conn, "flashcards", "lapse_count", "INTEGER NOT NULL DEFAULT 0")?;
+    if added_review_count || added_lapse_count {
+        rebuild_counters(conn)?;
+    }
+
+    add_column(conn, "flashcards", "suspended", "BOOLEAN NOT NULL DEFAULT 0")?;
+
+    // Existing cards start out in id order.
+    if add_column(conn, "flashcards", "order_index", "INTEGER NOT NULL DEFAULT 0")? {
+        conn.execute("UPDATE flashcards SET order_index = id", [])?;
+    }
+
+    // SM-2 picks up existing cards as if they'd climbed the ladder to their
+    // level one correct answer at a time.
+    add_column(conn, "flashcards", "ease_factor", "REAL NOT NULL DEFAULT 2.5")?;
+    if add_column(conn, "flashcards", "repetitions", "INTEGER NOT NULL DEFAULT 0")? {
+        conn.execute("UPDATE flashcards SET repetitions = MAX(level - 1, 0)", [])?;
+    }
+    if add_column(conn, "flashcards", "interval_days", "INTEGER NOT NULL DEFAULT 0")? {
+        for level in 1..=6 {
+            let op = if level < 6 { "=" } else { ">=" };
+            conn.execute(
+                &format!("UPDATE flashcards SET interval_days = ?1 WHERE level {} ?2", op),
+                params![scheduler::level_to_date(level), level],
+            )?;
+        }
+    }
+
+    // See `identity`.
+    add_column(conn, "flashcards", "front_hash", "TEXT")?;
+    conn.execute("CREATE INDEX IF NOT EXISTS flashcards_front_hash ON flashcards (deck_id, front_hash)", [])?;
+
+    Ok(())
+}
+
+fn has_foreign_keys(conn: &Connection, table: &str) -> Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA foreign_key_list({})", table))?;
+    let exists = stmt.exists([])?;
+    Ok(exists)
+}
+
+// Copies `table` into a new one created by `create`, then swaps them.
+fn rebuild_table(conn: &Connection, table: &str, create: fn(&str) -> String, columns: &str) -> Result<()> {
+    debug!("rebuild_table: {}", table);
+    let new = format!("{}_new", table);
+    conn.execute(&create(&new), [])?;
+    conn.execute(&format!("INSERT INTO {} ({1}) SELECT {1} FROM {2}", new, columns, table), [])?;
+    conn.execute(&format!("DROP TABLE {}", table), [])?;
+    conn.execute(&format!("ALTER TABLE {} RENAME TO {}", new, table), [])?;
+    Ok(())
+}
+
+// SQLite can't add a foreign key to an existing table, so `flashcards` and
+// `flashcard_log` are rebuilt with theirs. Rows the keys would reject were
+// unreachable anyway: cards of decks that are gone, and reviews of cards
+// that are gone. They're dropped first.
+fn add_foreign_keys(conn: &Connection) -> Result<()> {
+    if !has_foreign_keys(conn, "flashcards")? {
+        let orphans = conn.execute("DELETE FROM flashcards WHERE deck_id NOT IN (SELECT id FROM decks)", [])?;
+        if orphans > 0 {
+            warn!("migrate: removed {} cards of decks that no longer exist", orphans);
+        }
+        rebuild_table(conn, "flashcards", flashcards_table, FLASHCARDS_COLUMNS)?;
+        conn.execute("CREATE INDEX IF NOT EXISTS flashcards_front_hash ON flashcards (deck_id, front_hash)", [])?;
+    }
+
+    if !has_foreign_keys(conn, "flashcard_log")? {
+        let orphans = conn.execute("DELETE FROM flashcard_log WHERE question_id NOT IN (SELECT id FROM flashcards)", [])?;
+        if orphans > 0 {
+            warn!("migrate: removed {} reviews of cards that no longer exist", orphans);
+        }
+        rebuild_table(conn, "flashcard_log", flashcard_log_table, "question_id, answer, reviewed_at")?;
+    }
+
+    Ok(())
+}
+
+// Reviews logged before this column existed keep a NULL grade; `history`
+// shows whether they were right instead.
+fn add_review_grades(conn: &Connection) -> Result<()> {
+    add_column(conn, "flashcard_log", "grade", "TEXT")?;
+    Ok(())
+}
+
+// The alternating deck direction was called `both` until `quiz --both` took
+// the name for a random side per card.
+fn rename_both_direction(conn: &Connection) -> Result<()> {
+    conn.execute("UPDATE decks SET default_direction = 'alternate' WHERE default_direction = 'both'", [])?;
+    Ok(())
+}
+
 fn insert_deck(conn: &Connection, deck: &Deck) -> Result<()> {
+    debug!("insert_deck: name={} color={:?} direction={}", deck.name, deck.color, deck.direction.name());
     conn.execute(
-        "INSERT INTO decks (name) VALUES (?1)",
-        params![deck.name],
+        "INSERT INTO decks (name, color, default_direction) VALUES (?1, ?2, ?3)",
+        params![deck.name, deck.color, deck.direction.name()],
     )?;
     Ok(())
 }
 
+/// Inserts `card` at the end of its deck's sequence; see `manage::reorder`.
 fn insert_flashcard(conn: &Connection, card: &Flashcard) -> Result<()> {
+    debug!("insert_flashcard: deck_id={} added={} next={} level={}", card.deck_id, card.added, card.next, card.level);
+    trace!("insert_flashcard: front={:?} back={:?}", card.front, card.back);
+    conn.execute(
+        "INSERT INTO flashcards (deck_id, front, back, added, next, level, ease_factor, repetitions, interval_days, note_type_id, source, suspended, front_hash, order_index)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, (SELECT COALESCE(MAX(order_index), 0) + 1 FROM flashcards WHERE deck_id = ?1))",
+        params![
+            card.deck_id, card.front, card.back, card.added, card.next, card.level, card.ease_factor, card.repetitions, card.interval_days,
+            card.note_type_id, card.source, card.suspended, identity::front_hash(&card.front),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Replaces the front and back of card `id`, keeping its schedule and history.
+fn update_flashcard_text(conn: &Connection, id: i32, front: &str, back: &str) -> Result<usize> {
+    debug!("update_flashcard_text: id={}", id);
+    trace!("update_flashcard_text: front={:?} back={:?}", front, back);
+    conn.execute(
+        "UPDATE flashcards SET front = ?1, back = ?2, front_hash = ?3 WHERE id = ?4",
+        params![front, back, identity::front_hash(front), id],
+    )
+}
+
+/// Saves the outcome of grading card `id`.
+fn update_flashcard_schedule(conn: &Connection, id: i32, review: &Review) -> Result<()> {
+    debug!("update_flashcard_schedule: id={} level={} next={} ease_factor={:.2} interval_days={}", id, review.level, review.next, review.ease_factor, review.interval_days);
     conn.execute(
-        "INSERT INTO flashcards (deck_id, front, back, added, next, level) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-        params![card.deck_id, card.front, card.back, card.added, card.next, card.level],
+        "UPDATE flashcards SET level = ?1, next = ?2, ease_factor = ?3, repetitions = ?4, interval_days = ?5 WHERE id = ?6",
+        params![review.level, review.next, review.ease_factor, review.repetitions, review.interval_days, id],
     )?;
     Ok(())
 }
 
-fn update_flashcard_level(conn: &Connection, id: i32, level: i32, next: &NaiveDate) -> Result<()> {
+/// Bumps the denormalized review counters; `lapse` is set for a wrong answer.
+fn increment_review_counts(conn: &Connection, id: i32, lapse: bool) -> Result<()> {
+    debug!("increment_review_counts: id={} lapse={}", id, lapse);
     conn.execute(
-        "UPDATE flashcards SET level = ?1, next = ?2 WHERE id = ?3",
-        params![level, next, id],
+        "UPDATE flashcards SET review_count = review_count + 1, lapse_count = lapse_count + ?1 WHERE id = ?2",
+        params![lapse as i32, id],
     )?;
     Ok(())
 }
 
 fn insert_flashcard_log(conn: &Connection, log: &FlashcardLog) -> Result<()> {
+    debug!("insert_flashcard_log: question_id={} grade={} reviewed_at={}", log.question_id, log.grade, log.reviewed_at);
+    conn.execute(
+        "INSERT INTO flashcard_log (question_id, answer, reviewed_at, grade) VALUES (?1, ?2, ?3, ?4)",
+        params![log.question_id, log.grade.is_correct(), log.reviewed_at, log.grade.to_string()],
+    )?;
+    Ok(())
+}
+
+fn insert_card_event(conn: &Connection, card_id: i32, event: &str, detail: &str) -> Result<()> {
+    debug!("insert_card_event: card_id={} event={} detail={}", card_id, event, detail);
     conn.execute(
-        "INSERT INTO flashcard_log (question_id, answer) VALUES (?1, ?2)",
-        params![log.question_id, log.answer],
+        "INSERT INTO card_events (card_id, event, detail, at) VALUES (?1, ?2, ?3, ?4)",
+        params![card_id, event, detail, Local::now().naive_local()],
     )?;
     Ok(())
 }
 
+fn insert_session(conn: &Connection, session: &Session) -> Result<()> {
+    debug!("insert_session: decks={} cards_reviewed={} correct={}", session.decks, session.cards_reviewed, session.correct);
+    conn.execute(
+        "INSERT INTO sessions (started_at, ended_at, decks, cards_reviewed, correct) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![session.started_at, session.ended_at, session.decks, session.cards_reviewed, session.correct],
+    )?;
+    Ok(())
+}
+
+/// Counts the sessions over exactly `decks` that started on `day`.
+fn count_sessions_on(conn: &Connection, decks: &str, day: &NaiveDate) -> Result<u32> {
+    debug!("count_sessions_on: decks={} day={}", decks, day);
+    conn.query_row(
+        "SELECT COUNT(*) FROM sessions WHERE decks = ?1 AND date(started_at) = ?2",
+        params![decks, day],
+        |row| row.get(0),
+    )
+}
+
+fn get_meta(conn: &Connection, key: &str) -> Result<Option<String>> {
+    debug!("get_meta: key={}", key);
+    let mut stmt = conn.prepare("SELECT value FROM meta WHERE key = ?1")?;
+    let mut rows = stmt.query_map(params![key], |row| row.get(0))?;
+    rows.next().transpose()
+}
+
+fn set_meta(conn: &Connection, key: &str, value: &str) -> Result<()> {
+    debug!("set_meta: key={} value={}", key, value);
+    conn.execute(
+        "INSERT INTO meta (key, value) VALUES (?1, ?2) ON CONFLICT(key) DO UPDATE SET value = ?2",
+        params![key, value],
+    )?;
+    Ok(())
+}
+
+/// A savepoint on `conn`, rolled back unless committed.
+///
+/// Unlike `Connection::unchecked_transaction`, savepoints nest, so commands
+/// that group their writes still work inside the transaction `--dry-run` wraps
+/// around the whole command.
+struct Savepoint<'a> {
+    conn: &'a Connection,
+    name: &'static str,
+    done: bool,
+}
+
+impl<'a> Savepoint<'a> {
+    fn new(conn: &'a Connection, name: &'static str) -> Result<Savepoint<'a>> {
+        debug!("SAVEPOINT {}", name);
+        conn.execute_batch(&format!("SAVEPOINT {}", name))?;
+        Ok(Savepoint { conn, name, done: false })
+    }
+
+    fn commit(mut self) -> Result<()> {
+        self.done = true;
+        self.conn.execute_batch(&format!("RELEASE {}", self.name))
+    }
+
+    fn rollback(mut self) -> Result<()> {
+        self.done = true;
+        self.conn.execute_batch(&format!("ROLLBACK TO {0}; RELEASE {0}", self.name))
+    }
+}
+
+impl std::ops::Deref for Savepoint<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn
+    }
+}
+
+impl Drop for Savepoint<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.conn.execute_batch(&format!("ROLLBACK TO {0}; RELEASE {0}", self.name));
+        }
+    }
+}
+
+/// Prints a statement about to run, with its parameters bound, for the
+/// global `--explain-sql` flag. It goes to stderr so output stays parseable.
+fn explain(sql: &str) {
+    eprintln!("sql: {}", sql.trim());
+}
+
+// Set by the global `--dry-run` flag.
+static DRY_RUN: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Whether this run is a `--dry-run`. Database writes are rolled back by
+/// `main` regardless; commands check this before writing files.
+fn dry_run() -> bool {
+    DRY_RUN.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+// Set by `fail!` when a command reports an error.
+static FAILED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+fn set_failed() {
+    FAILED.store(true, std::sync::atomic::Ordering::Relaxed);
+}
+
 fn init_db(conn: &Connection) {
-    create_tables(&conn).unwrap();
+    create_tables(conn).unwrap();
+    migrate(conn).unwrap();
 }
 
-fn add(conn: &Connection, args: &Vec<String>) {
+/// `init [--from <template>] [--list-templates]`: creates the database's
+/// tables, empty unless `--from` adds one of the starter decks that
+/// `--list-templates` shows.
+fn init(conn: &Connection, config: &config::Config, args: &[String]) {
+    let mut args = args.to_vec();
+    if take_flag(&mut args, "--list-templates") {
+        starter::list();
+        return;
+    }
+    let starter = match take_value(&mut args, "--from").map(|name| name.map(|name| starter::find(&name)).transpose()) {
+        Ok(Ok(starter)) => starter,
+        Ok(Err(e)) | Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    init_db(conn);
+    if let Some(starter) = starter {
+        import::import_starter(conn, config, starter.name, starter.text);
+    }
+}
+
+fn add(conn: &Connection, config: &config::Config, args: &[String]) {
     if args.len() < 3 {
-        println!("Missing <subcommand>");
+        fail!("Missing <subcommand>");
         return;
     }
 
     let command = &args[2];
     match command.as_str() {
         "deck" => {
+            let mut args = args.to_vec();
+            // `{"id":N,"name":"..."}`, so scripts can add cards without looking the id up.
+            let as_json = take_flag(&mut args, "--json");
+            let color = match take_value(&mut args, "--color") {
+                Ok(color) => color,
+                Err(e) => {
+                    fail!("{}", e);
+                    return;
+                }
+            };
+            if let Some(color) = &color {
+                if deck::parse_color(color).is_none() {
+                    fail!("Unknown color: {}", color);
+                    return;
+                }
+            }
+            let direction = match take_value(&mut args, "--direction") {
+                Ok(None) => deck::Direction::Front,
+                Ok(Some(name)) => match deck::Direction::parse(&name) {
+                    Some(direction) => direction,
+                    None => {
+                        fail!("Unknown direction: {} (supported: front, back, alternate, random)", name);
+                        return;
+                    }
+                },
+                Err(e) => {
+                    fail!("{}", e);
+                    return;
+                }
+            };
+
             if args.len() < 4 {
-                println!("Missing <deck_name>");
+                fail!("Missing <deck_name>");
                 return;
             }
 
             let deck = Deck {
                 name: args[3].to_string(),
+                color,
+                direction,
             };
-            match insert_deck(&conn, &deck) {
+            match insert_deck(conn, &deck) {
                 Ok(_) => {
-                    println!("Deck added: {}", deck.name);
+                    let id = conn.last_insert_rowid();
+                    if as_json {
+                        println!("{{\"id\":{},\"name\":{}}}", id, json::string(&deck.name));
+                    } else {
+                        println!("Deck added: {} (id {})", deck.name, id);
+                    }
                 },
                 Err(e) => {
-                    println!("Error adding deck: {}", e);
+                    fail!("Error adding deck: {}", e);
                 }
             }
         },
-        "cards" => {
-            let path = args[3].to_string();
-            let file = match read_to_string(path) {
-                Ok(file) => file,
-                Err(e) => {
-                    println!("Error reading file: {}", e);
-                    return;
-                }
-            };
-
-            let cards: Vec<&str> = file.split("----").collect();
-
-            let name: Vec<&str> = cards[0].split(":").collect();
-            let name = name[1].trim();
+        "card" => import::add_card(conn, config, args),
+        "cards" => import::add_cards(conn, config, args),
+        "note-type" => template::add_note_type(conn, args),
+        "note" => template::add_note(conn, args),
+        _ => {
+            fail!("Unknown add command: {}", command);
+        }
+    }
+}
 
-            let deck_id = match get_deck_id_from_name(&conn, name) {
-                Ok(id) => id,
-                Err(e) => {
-                    println!("Error getting deck id: {}", e);
-                    return;
-                }
-            };
+fn delete(conn: &Connection, args: &[String]) {
+    if args.len() < 3 {
+        fail!("Missing <subcommand>");
+        return;
+    }
 
-            let added_date = Local::now().naive_utc().date();
+    let command = &args[2];
+    match command.as_str() {
+        "deck" => manage::delete_deck(conn, args),
+        "card" => manage::delete_card(conn, args),
+        _ => {
+            fail!("Unknown delete command: {}", command);
+        }
+    }
+}
 
-            let mut cards = parse_cards(deck_id, &cards[1..], &added_date);
+/// `list [decks]`, `list cards <deck>`, or `list <deck>` for short.
+fn list(conn: &Connection, args: &[String]) {
+    if args.len() < 3 {
+        deck::list_decks(conn);
+        return;
+    }
 
-            for card in &mut cards {
-                match insert_flashcard(&conn, &card) {
-                    Ok(_) => {
-                        println!("Flashcard added: {}", card.front);
-                        let row_id = conn.last_insert_rowid();
-                        card.id = row_id as i32;
-                    },
-                    Err(e) => {
-                        println!("Error adding flashcard: {}", e);
-                    }
-                }
-            }
-        },
+    let command = &args[2];
+    match command.as_str() {
+        "decks" => deck::list_decks(conn),
+        "cards" => report::list_cards(conn, args),
         _ => {
-            println!("Unknown add command: {}", command);
+            let mut args = args.to_vec();
+            args.insert(2, "cards".to_string());
+            report::list_cards(conn, &args);
         }
     }
 }
 
 fn get_deck_id_from_name(conn: &Connection, name: &str) -> Result<i32> {
+    debug!("get_deck_id_from_name: name={}", name);
     let mut stmt = conn.prepare("SELECT id FROM decks WHERE name = ?1")?;
     let id: i32 = stmt.query_row(params![name], |row| row.get(0))?;
     Ok(id)
 }
 
-fn parse_cards(deck_id: i32, cards: &[&str], added_date: &NaiveDate) -> Vec<Flashcard> {
-    let mut result: Vec<Flashcard> = Vec::new();
+fn get_deck_name(conn: &Connection, id: i32) -> Result<String> {
+    debug!("get_deck_name: id={}", id);
+    let mut stmt = conn.prepare("SELECT name FROM decks WHERE id = ?1")?;
+    stmt.query_row(params![id], |row| row.get(0))
+}
 
-    for card in cards {
-        let sides: Vec<&str> = card.split("<>").collect();
-        
-        if sides.len() != 2 {
-            continue;
-        }
+/// The calendar date cards are added, scheduled and selected as due by: the
+/// local one, so "today" ends at the user's midnight.
+fn today() -> NaiveDate {
+    Local::now().naive_local().date()
+}
 
-        let c = Flashcard {
-            id: -1, // dummy value
-            deck_id,
-            front: sides[0].to_string(),
-            back: sides[1].to_string(),
-            added: *added_date,
-            next: *added_date,
-            level: 1,
-        };
+// First non-empty line of a card side, for one-line summaries.
+fn first_line(text: &str) -> &str {
+    text.trim().lines().next().unwrap_or("")
+}
 
-        result.push(c);
+/// Resolves a deck given either its numeric id or its name.
+fn get_deck_id(conn: &Connection, deck_name: &str) -> Result<i32> {
+    match deck_name.parse() {
+        Ok(id) => Ok(id),
+        Err(_) => get_deck_id_from_name(conn, deck_name),
     }
+}
+
+// Columns read by `flashcard_from_row`, in order.
+fn flashcard_columns() -> String {
+    format!(
+        "id, deck_id, front, back, added, next, level, note_type_id, source, suspended, {}, ease_factor, repetitions, interval_days",
+        tags::tags_sql("flashcards.id"),
+    )
+}
+
+fn flashcard_from_row(row: &rusqlite::Row) -> Result<Flashcard> {
+    Ok(Flashcard {
+        id: row.get(0)?,
+        deck_id: row.get(1)?,
+        front: row.get(2)?,
+        back: row.get(3)?,
+        added: row.get(4)?,
+        next: row.get(5)?,
+        level: row.get(6)?,
+        note_type_id: row.get(7)?,
+        source: row.get(8)?,
+        suspended: row.get(9)?,
+        tags: tags::from_sql(row.get(10)?),
+        ease_factor: row.get(11)?,
+        repetitions: row.get(12)?,
+        interval_days: row.get(13)?,
+    })
+}
+
+fn get_flashcards(conn: &Connection, deck_id: i32) -> Result<Vec<Flashcard>> {
+    debug!("get_flashcards: deck_id={}", deck_id);
+    let sql = format!("SELECT {} FROM flashcards WHERE deck_id = ?1 ORDER BY id", flashcard_columns());
+    let mut stmt = conn.prepare(&sql)?;
+    let cards = stmt.query_map(params![deck_id], flashcard_from_row)?.collect();
+    cards
+}
 
-    result
+fn get_flashcard(conn: &Connection, id: i32) -> Result<Flashcard> {
+    debug!("get_flashcard: id={}", id);
+    let sql = format!("SELECT {} FROM flashcards WHERE id = ?1", flashcard_columns());
+    let mut stmt = conn.prepare(&sql)?;
+    stmt.query_row(params![id], flashcard_from_row)
 }
 
-fn clear_key_buffer() {
-    // Continuously read events until there are no more pending events
-    while poll(Duration::from_millis(0)).unwrap() {
-        if let Event::Key(_) = read().unwrap() {
-            // Simply discard the event
+/// Which cards count as due when compared against today's date.
+#[derive(Clone, Copy, PartialEq)]
+enum DueFilter {
+    // Due today or earlier.
+    Due,
+    // Strictly behind schedule: due before today.
+    Overdue,
+}
+
+impl DueFilter {
+    /// SQL predicate comparing `next` with the date bound to `param`.
+    fn sql(&self, param: &str) -> String {
+        match self {
+            DueFilter::Due => format!("next <= {}", param),
+            DueFilter::Overdue => format!("next < {}", param),
         }
     }
 }
 
-fn level_to_date(level: i32) -> i32 {
-    match level {
-        1 => 1,
-        2 => 4,
-        3 => 10,
-        4 => 25,
-        5 => 50,
-        _ => 1000,
-    }
+/// Puts a card's schedule back to how it was when the card was added, for
+/// `reset` and `swap --reset`.
+fn reset_schedule_sql() -> String {
+    format!("level = 1, ease_factor = {}, repetitions = 0, interval_days = 0", scheduler::INITIAL_EASE)
 }
 
-fn quiz(conn: &Connection, args: &Vec<String>) {
-    if args.len() < 3 {
-        println!("Missing <deck_name>");
-        return;
+/// A card's difficulty score, computed from the denormalized review counters:
+/// the share of its reviews that were lapses, 0 for cards never reviewed.
+/// Unlike a leech threshold this is continuous, for ranking cards.
+const DIFFICULTY_SQL: &str = "(CAST(lapse_count AS REAL) / MAX(review_count, 1))";
+
+/// SQL predicate shared by every command that selects or counts cards to
+/// study, so they agree on whether suspended cards are included.
+fn suspension_sql(include_suspended: bool) -> &'static str {
+    if include_suspended { "1" } else { "NOT suspended" }
+}
+
+/// Counts a deck's due cards without selecting their text.
+fn count_due(conn: &Connection, deck_id: i32, today: &NaiveDate, filter: DueFilter, include_suspended: bool) -> Result<i32> {
+    debug!("count_due: deck_id={} today={} include_suspended={}", deck_id, today, include_suspended);
+    let sql = format!("SELECT COUNT(*) FROM flashcards WHERE deck_id = ?1 and {} and {}", filter.sql("?2"), suspension_sql(include_suspended));
+    let mut stmt = conn.prepare(&sql)?;
+    stmt.query_row(params![deck_id, today], |row| row.get(0))
+}
+
+fn init_logger(level: Option<&str>) {
+    // RUST_LOG is honored as usual; an explicit --log-level takes precedence.
+    let mut builder = env_logger::Builder::from_default_env();
+    if let Some(level) = level {
+        builder.parse_filters(level);
     }
+    builder.init();
+}
 
-    let deck_name = &args[2];
+// The per-user data directory: $XDG_DATA_HOME or ~/.local/share, and the
+// usual places on macOS and Windows.
+fn data_dir() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home.map(|home| home.join("Library").join("Application Support"))
+    } else {
+        std::env::var_os("XDG_DATA_HOME").map(PathBuf::from).or_else(|| home.map(|home| home.join(".local").join("share")))
+    }
+}
 
-    let deck_id = match deck_name.parse() {
-        Ok(id) => id,
-        Err(_) => {
-            match get_deck_id_from_name(conn, deck_name) {
-                Ok(id) => id,
-                Err(e) => {
-                    println!("Error getting deck id: {}", e);
-                    return;
-                }
-            }
-        }
-    };
+// Where the database is: `FLASH_DB`, then `--db`, then `flashcards.db` in
+// the per-user data directory. Unlike settings (see `config`), the
+// environment wins over the flag, so a shell can pin one database for every
+// command run from it.
+//
+// Before the path was configurable the database was always opened from the
+// current directory; one found there is still used, with a note on stderr,
+// until it has been moved.
+fn db_path(flag: Option<String>) -> PathBuf {
+    if let Some(path) = std::env::var(config::DB_ENV).ok().or(flag) {
+        return PathBuf::from(path);
+    }
 
-    let mut stmt = conn.prepare("SELECT id, level, front, back FROM flashcards WHERE deck_id = ?1 and next <= ?2").unwrap();
-    let mut rows: Vec<Result<(i32, i32, String, String)>> = stmt.query_map(params![deck_id, Local::now().naive_utc().date()], |row| {
-        Ok((
-            row.get::<_, i32>(0)?,
-            row.get::<_, i32>(1)?,
-            row.get::<_, String>(2)?, 
-            row.get::<_, String>(3)?
-        ))
-    }).unwrap().collect();
-
-    let mut rng = thread_rng();
-    rows.shuffle(&mut rng);
-
-    clear_key_buffer();
-
-    for row in rows {
-        let (id, level, front, back) = row.unwrap();
-        println!("{}", front);
-        println!("press enter to flip");
-
-        loop {
-            // Wait for an event
-            if let Event::Key(key_event) = read().unwrap() {
-                // Check if it's a key press event
-                match key_event.code {
-                    KeyCode::Enter => {
-                        break;
-                    }
-                    _ => (),
-                }
-            }
-        }
+    let legacy = PathBuf::from("flashcards.db");
+    match data_dir().map(|dir| dir.join("flash").join("flashcards.db")) {
+        Some(path) if path.exists() || !legacy.exists() => path,
+        Some(path) => {
+            eprintln!("Using ./flashcards.db; move it to {} (or set FLASH_DB) to use it from any directory", path.display());
+            legacy
+        },
+        None => legacy,
+    }
+}
 
-        println!("{}", back);
-        println!("Press - O: 1, X: 2");
-
-        loop {
-            // Wait for an event
-            if let Event::Key(key_event) = read().unwrap() {
-                // Check if it's a key press event
-                match key_event.code {
-                    KeyCode::Char('1') => {
-                        let next_date = Local::now().naive_local().date()
-                            .checked_add_days(
-                                Days::new(level_to_date(level + 1) as u64)
-                            ).unwrap();
-                        update_flashcard_level(conn, id, level + 1, &next_date).unwrap();
-                        insert_flashcard_log(conn, &FlashcardLog { question_id: id, answer: true }).unwrap();
-                        break;
-                    }
-                    KeyCode::Char('2') => {
-                        let next_date = Local::now().naive_local().date()
-                            .checked_add_days(
-                                Days::new(level_to_date(level) as u64)
-                            ).unwrap();
-                        let next_level = if level > 1 { level - 1 } else { 1 };
-
-                        update_flashcard_level(conn, id, next_level, &next_date).unwrap();
-                        insert_flashcard_log(conn, &FlashcardLog { question_id: id, answer: false }).unwrap();
-                        break;
-                    }
-                    _ => (),
-                }
-            }
-        }
+/// Exits with status 1 when the command reported an error: an unknown command,
+/// missing or invalid arguments, or a database or file error. Messages
+/// such as "Nothing is due" aren't errors.
+fn main() -> ExitCode {
+    run();
+    if FAILED.load(std::sync::atomic::Ordering::Relaxed) {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
     }
 }
 
-fn main() {
-    let args = std::env::args().collect::<Vec<_>>();
+fn run() {
+    let mut args = std::env::args().collect::<Vec<_>>();
+
+    let log_level = match take_value(&mut args, "--log-level") {
+        Ok(level) => level,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+    init_logger(log_level.as_deref());
+
+    if take_flag(&mut args, "--no-color") {
+        deck::disable_color();
+    }
+    let dry_run = take_flag(&mut args, "--dry-run");
+    let explain_sql = take_flag(&mut args, "--explain-sql");
+    let db = match take_value(&mut args, "--db") {
+        Ok(db) => db_path(db),
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
 
     match args.len() {
         0 | 1 => {
-            println!("Missing <command>");
+            fail!("Missing <command>");
             return;
         },
         _ => {}
     }
 
-    let command = &args[1];
-    let conn = Connection::open("flashcards.db").unwrap();
+    let command = args[1].clone();
+    // `quiz --practice` promises not to write, so it can't, even by accident.
+    let read_only = command == "quiz" && args.iter().any(|arg| arg == "--practice");
+    let flags = if read_only { OpenFlags::SQLITE_OPEN_READ_ONLY } else { OpenFlags::default() };
+    if command == "init" {
+        if let Some(dir) = db.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            if let Err(e) = std::fs::create_dir_all(dir) {
+                fail!("Error creating {}: {}", dir.display(), e);
+                return;
+            }
+        }
+    }
+    let mut conn = match Connection::open_with_flags(&db, flags) {
+        Ok(conn) => conn,
+        Err(e) => {
+            fail!("Error opening database {}: {}", db.display(), e);
+            if !db.exists() {
+                println!("Run flash init to create it");
+            }
+            return;
+        }
+    };
+    debug!("opened {}", db.display());
+
+    if let Err(e) = migrate(&conn) {
+        fail!("Error upgrading database: {}", e);
+        if read_only {
+            println!("Run flash once without --practice to upgrade it");
+        }
+        return;
+    }
+
+    // After migrating, which may rebuild tables the keys point at.
+    if let Err(e) = conn.execute_batch("PRAGMA foreign_keys = ON") {
+        fail!("Error enabling foreign keys: {}", e);
+        return;
+    }
+
+    // Set up after migrating, so only the command's own statements are shown.
+    if explain_sql {
+        conn.trace(Some(explain));
+    }
+
+    // Validation reports broken settings itself rather than failing on them.
+    if command == "config" && args.get(2).map(String::as_str) == Some("validate") {
+        if !config::validate(&conn, &mut args) {
+            set_failed();
+        }
+        return;
+    }
+
+    let config = match config::Config::load(&conn, &mut args) {
+        Ok(config) => config,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    // Everything the command writes happens inside one transaction that is
+    // rolled back at the end, so it runs for real but nothing is saved.
+    if dry_run {
+        DRY_RUN.store(true, std::sync::atomic::Ordering::Relaxed);
+        if let Err(e) = conn.execute_batch("BEGIN") {
+            fail!("Error starting dry run: {}", e);
+            return;
+        }
+    }
 
     match command.as_str() {
-        "init" => init_db(&conn),
-        "add" => add(&conn, &args),
-        "quiz" => quiz(&conn, &args),
+        "init" => init(&conn, &config, &args),
+        "add" => add(&conn, &config, &args),
+        "quiz" => quiz::quiz(&conn, &config, &args),
+        "batch-grade" => quiz::batch_grade(&conn, &config, &args),
+        "list" => list(&conn, &args),
+        "delete" => delete(&conn, &args),
+        "enable" => deck::set_active(&conn, &args, true),
+        "disable" => deck::set_active(&conn, &args, false),
+        "pin" => deck::set_pinned(&conn, &args, true),
+        "unpin" => deck::set_pinned(&conn, &args, false),
+        "recolor" => deck::recolor(&conn, &args),
+        "reweight" => deck::reweight(&conn, &args),
+        "direction" => deck::set_direction(&conn, &args),
+        "export" => export::export(&conn, &args),
+        "import" => import::import(&conn, &config, &args),
+        "formats" => format::formats(),
+        "due" => deck::due(&conn, &args),
+        "reset" => manage::reset(&conn, &args),
+        "swap" => manage::swap(&conn, &args),
+        "suspend" => manage::set_suspended(&conn, &args, true),
+        "unsuspend" => manage::set_suspended(&conn, &args, false),
+        "tag" => tags::tag(&conn, &args),
+        "edit" => manage::edit(&conn, &config, &args),
+        "promote" => manage::adjust_level(&conn, &args, 1),
+        "demote" => manage::adjust_level(&conn, &args, -1),
+        "rebuild" => manage::rebuild(&conn),
+        "reorder" => manage::reorder(&conn, &args),
+        "merge" => manage::merge(&conn, &args),
+        "trim" => manage::trim(&conn, &args),
+        "config" => config::config(&conn, &config, &args),
+        "retention" => report::retention(&conn, &args),
+        "history" => report::history(&conn, &args),
+        "peek" => report::peek(&conn, &args),
+        "snapshot" => report::snapshot(&conn, &args),
+        "sessions" => report::sessions(&conn, &args),
+        "stats" => report::stats(&conn, &args),
+        "compare" => compare::compare(&conn, &config, &args),
+        "search" => report::search(&conn, &args),
+        "study-plan" => plan::study_plan(&conn, &args),
+        "typos" => typos::typos(&conn, &args),
         _ => {
-            println!("Unknown command: {}", command);
+            fail!("Unknown command: {}", command);
+        }
+    }
+
+    if dry_run {
+        match conn.execute_batch("ROLLBACK") {
+            // Kept off stdout when it's machine-readable output.
+            Ok(_) if args.iter().any(|arg| arg == "--json") => eprintln!("Dry run: no changes were saved"),
+            Ok(_) => println!("Dry run: no changes were saved"),
+            Err(e) => fail!("Error rolling back dry run: {}", e),
         }
     }
 }
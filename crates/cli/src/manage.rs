@@ -0,0 +1,964 @@
+// Commands that change or remove existing cards.
+
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::io::Read;
+
+use rusqlite::{params, Connection, Result};
+use chrono::NaiveDate;
+use log::debug;
+
+use crate::args::{take_flag, take_parsed, take_value};
+use crate::config::Config;
+use crate::confirm::{confirm, examples};
+use crate::identity::{fill_front_hashes, take_match_on, MatchOn};
+use crate::order::resequence;
+use crate::parse::{check_sides, EmptySides};
+use crate::scheduler::{add_interval, level_to_date, MAX_LEVEL};
+use crate::{answer, dry_run, first_line, get_deck_id, get_deck_name, get_flashcard, get_flashcards, insert_card_event, rebuild_counters, Flashcard, reset_schedule_sql, today, update_flashcard_text, Savepoint};
+
+fn card_lines(cards: &[Flashcard]) -> Vec<String> {
+    cards.iter()
+        .map(|card| format!("{}: {} (level {}, next {})", card.id, first_line(&card.front), card.level, card.next))
+        .collect()
+}
+
+fn reset_deck(conn: &Connection, deck_id: i32, today: &NaiveDate) -> Result<usize> {
+    debug!("reset_deck: deck_id={} today={}", deck_id, today);
+    conn.execute(
+        &format!("UPDATE flashcards SET {}, next = ?1 WHERE deck_id = ?2", reset_schedule_sql()),
+        params![today, deck_id],
+    )
+}
+
+/// `reset <deck_name> [--yes]`: puts every card in a deck back to level 1, due today.
+pub fn reset(conn: &Connection, args: &[String]) {
+    let mut args = args.to_vec();
+    let yes = take_flag(&mut args, "--yes");
+
+    if args.len() < 3 {
+        fail!("Missing <deck_name>");
+        return;
+    }
+
+    let deck_id = match get_deck_id(conn, &args[2]) {
+        Ok(id) => id,
+        Err(e) => {
+            fail!("Error getting deck id: {}", e);
+            return;
+        }
+    };
+
+    let cards = match get_flashcards(conn, deck_id) {
+        Ok(cards) => cards,
+        Err(e) => {
+            fail!("Error reading flashcards: {}", e);
+            return;
+        }
+    };
+
+    if cards.is_empty() {
+        println!("No cards in deck {}", args[2]);
+        return;
+    }
+
+    let today = today();
+    let summary = format!(
+        "This will reset {} cards in deck {} to level 1, due {}:\n{}",
+        cards.len(),
+        args[2],
+        today,
+        examples(&card_lines(&cards), cards.len()),
+    );
+    if !confirm(summary.trim_end(), yes) {
+        return;
+    }
+
+    match reset_deck(conn, deck_id, &today) {
+        Ok(count) => {
+            println!("Reset {} cards", count);
+        },
+        Err(e) => {
+            fail!("Error resetting deck: {}", e);
+        }
+    }
+}
+
+/// `rebuild`: recomputes the per-card review counters from the review log.
+pub fn rebuild(conn: &Connection) {
+    match rebuild_counters(conn) {
+        Ok(count) => {
+            println!("Rebuilt review counters for {} cards", count);
+        },
+        Err(e) => {
+            fail!("Error rebuilding review counters: {}", e);
+        }
+    }
+}
+
+fn swap_deck(conn: &Connection, deck_id: i32, reset_to: Option<&NaiveDate>) -> Result<usize> {
+    debug!("swap_deck: deck_id={} reset_to={:?}", deck_id, reset_to);
+    // SQLite evaluates every right-hand side against the old row, so this
+    // swaps. The new fronts are hashed afterwards.
+    let swapped = match reset_to {
+        Some(today) => conn.execute(
+            &format!("UPDATE flashcards SET front = back, back = front, front_hash = NULL, {}, next = ?1 WHERE deck_id = ?2 AND note_type_id IS NULL", reset_schedule_sql()),
+            params![today, deck_id],
+        )?,
+        None => conn.execute(
+            "UPDATE flashcards SET front = back, back = front, front_hash = NULL WHERE deck_id = ?1 AND note_type_id IS NULL",
+            params![deck_id],
+        )?,
+    };
+    fill_front_hashes(conn)?;
+    Ok(swapped)
+}
+
+/// `swap <deck_name> [--reset] [--yes]`: swaps front and back of every card in a deck.
+///
+/// Cards built from a note type are rendered from its templates and are left
+/// alone. `--reset` also puts the swapped cards back to level 1, due today,
+/// since they're effectively new questions; that asks for confirmation.
+/// With the global `--dry-run`, it only prints what it would change.
+pub fn swap(conn: &Connection, args: &[String]) {
+    let mut args = args.to_vec();
+    let reset = take_flag(&mut args, "--reset");
+    let yes = take_flag(&mut args, "--yes");
+
+    if args.len() < 3 {
+        fail!("Missing <deck_name>");
+        return;
+    }
+
+    let deck_id = match get_deck_id(conn, &args[2]) {
+        Ok(id) => id,
+        Err(e) => {
+            fail!("Error getting deck id: {}", e);
+            return;
+        }
+    };
+
+    let cards = match get_flashcards(conn, deck_id) {
+        Ok(cards) => cards,
+        Err(e) => {
+            fail!("Error reading flashcards: {}", e);
+            return;
+        }
+    };
+    let (cards, templated): (Vec<Flashcard>, Vec<Flashcard>) = cards.into_iter().partition(|card| card.note_type_id.is_none());
+
+    if !templated.is_empty() {
+        println!("Skipping {} cards built from a note type", templated.len());
+    }
+    if cards.is_empty() {
+        println!("No cards to swap in deck {}", args[2]);
+        return;
+    }
+
+    let lines: Vec<String> = cards.iter()
+        .map(|card| format!("{}: {} <> {}", card.id, first_line(&card.back), first_line(&card.front)))
+        .collect();
+    let today = today();
+    let summary = format!(
+        "This will swap front and back of {} cards in deck {}{}:\n{}",
+        cards.len(),
+        args[2],
+        if reset { format!(" and reset them to level 1, due {}", today) } else { String::new() },
+        examples(&lines, cards.len()),
+    );
+
+    if dry_run() {
+        println!("{}", summary.trim_end());
+        return;
+    }
+    if reset && !confirm(summary.trim_end(), yes) {
+        return;
+    }
+
+    match swap_deck(conn, deck_id, if reset { Some(&today) } else { None }) {
+        Ok(count) => {
+            println!("Swapped {} cards", count);
+        },
+        Err(e) => {
+            fail!("Error swapping cards: {}", e);
+        }
+    }
+}
+
+fn update_suspended(conn: &Connection, id: i32, suspended: bool) -> Result<usize> {
+    debug!("update_suspended: id={} suspended={}", id, suspended);
+    conn.execute("UPDATE flashcards SET suspended = ?1 WHERE id = ?2", params![suspended, id])
+}
+
+/// `suspend <card_id>` / `unsuspend <card_id>`
+///
+/// Suspended cards keep their schedule and history but are left out of
+/// `quiz` and its counts unless `--include-suspended` is given.
+pub fn set_suspended(conn: &Connection, args: &[String], suspended: bool) {
+    if args.len() < 3 {
+        fail!("Missing <card_id>");
+        return;
+    }
+
+    let id: i32 = match args[2].parse() {
+        Ok(id) => id,
+        Err(_) => {
+            fail!("Invalid card id: {}", args[2]);
+            return;
+        }
+    };
+
+    match update_suspended(conn, id, suspended) {
+        Ok(0) => {
+            fail!("No card with id {}", id);
+        },
+        Ok(_) => {
+            println!("Card {}: {}", if suspended { "suspended" } else { "unsuspended" }, id);
+        },
+        Err(e) => {
+            fail!("Error updating card: {}", e);
+        }
+    }
+}
+
+/// `edit <card_id> [--front <text>] [--back <text>]`: fixes a card's text.
+///
+/// Only the sides given change; the card keeps its level, schedule, added
+/// date and review history. Cards built from a note type are rendered from
+/// their fields and can't be edited this way. Empty sides follow the
+/// `empty_sides` setting, as for `add card`.
+pub fn edit(conn: &Connection, config: &Config, args: &[String]) {
+    let mut args = args.to_vec();
+    let (front, back) = match (take_value(&mut args, "--front"), take_value(&mut args, "--back")) {
+        (Ok(front), Ok(back)) => (front, back),
+        (Err(e), _) | (_, Err(e)) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    if args.len() < 3 {
+        fail!("Missing <card_id>");
+        return;
+    }
+    if front.is_none() && back.is_none() {
+        fail!("Nothing to edit: pass --front and/or --back");
+        return;
+    }
+
+    let id: i32 = match args[2].parse() {
+        Ok(id) => id,
+        Err(_) => {
+            fail!("Invalid card id: {}", args[2]);
+            return;
+        }
+    };
+
+    let card = match get_flashcard(conn, id) {
+        Ok(card) => card,
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            fail!("No card with id {}", id);
+            return;
+        },
+        Err(e) => {
+            fail!("Error reading flashcard: {}", e);
+            return;
+        }
+    };
+    if card.note_type_id.is_some() {
+        fail!("Card {} is built from a note type; its front and back can't be edited", id);
+        return;
+    }
+
+    let front = front.unwrap_or_else(|| card.front.clone());
+    let back = back.unwrap_or_else(|| card.back.clone());
+    if front == card.front && back == card.back {
+        println!("Card {} unchanged", id);
+        return;
+    }
+    if let Some(problem) = check_sides(&front, &back) {
+        match config.empty_sides() {
+            EmptySides::Reject => {
+                fail!("Can't edit card: {}", problem);
+                return;
+            },
+            EmptySides::Warn => println!("Warning: {}", problem),
+        }
+    }
+
+    if let Err(e) = update_flashcard_text(conn, id, &front, &back) {
+        fail!("Error updating card: {}", e);
+        return;
+    }
+    println!("Card {} updated", id);
+    for (side, before, after) in [("front", &card.front, &front), ("back", &card.back, &back)] {
+        if before != after {
+            println!("  {}: {:?} -> {:?}", side, before, after);
+        }
+    }
+}
+
+// Moves a card from level `from` to `level` and records the change in
+// `card_events`.
+fn update_level(conn: &Connection, id: i32, from: i32, level: i32, today: &NaiveDate) -> Result<NaiveDate> {
+    debug!("update_level: id={} level={} -> {}", id, from, level);
+    let tx = Savepoint::new(conn, "update_level")?;
+    // As if the card had climbed to `level` one correct answer at a time.
+    let interval = level_to_date(level);
+    let next = add_interval(*today, interval as u64);
+    tx.execute(
+        "UPDATE flashcards SET level = ?1, next = ?2, repetitions = ?3, interval_days = ?4 WHERE id = ?5",
+        params![level, next, level - 1, interval, id],
+    )?;
+    let event = if level > from { "Promote" } else { "Demote" };
+    insert_card_event(&tx, id, event, &format!("level {} -> {}", from, level))?;
+    tx.commit()?;
+    Ok(next)
+}
+
+// The level promoting (`step` 1) or demoting (-1) a card moves it to, or
+// None at either end. The quiz can take cards past MAX_LEVEL; promoting one
+// of those leaves it there instead of pulling it back down.
+fn adjusted_level(level: i32, step: i32) -> Option<i32> {
+    if (step > 0 && level >= MAX_LEVEL) || (step < 0 && level <= 1) {
+        return None;
+    }
+    Some(level + step)
+}
+
+/// `promote <card_id>` / `demote <card_id>`: moves a card one level up or
+/// down by hand, for when the scheduler has it wrong, and reschedules it from
+/// today with the new level's interval. The change is recorded in
+/// `card_events`, so `history` shows it.
+pub fn adjust_level(conn: &Connection, args: &[String], step: i32) {
+    if args.len() < 3 {
+        fail!("Missing <card_id>");
+        return;
+    }
+
+    let id: i32 = match args[2].parse() {
+        Ok(id) => id,
+        Err(_) => {
+            fail!("Invalid card id: {}", args[2]);
+            return;
+        }
+    };
+
+    let card = match get_flashcard(conn, id) {
+        Ok(card) => card,
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            fail!("No card with id {}", id);
+            return;
+        },
+        Err(e) => {
+            fail!("Error reading flashcard: {}", e);
+            return;
+        }
+    };
+
+    let level = match adjusted_level(card.level, step) {
+        Some(level) => level,
+        None if step > 0 => {
+            println!("Card {} is already at the top level", id);
+            return;
+        },
+        None => {
+            println!("Card {} is already at level {}", id, card.level);
+            return;
+        }
+    };
+
+    let today = today();
+    match update_level(conn, id, card.level, level, &today) {
+        Ok(next) => println!("Card {}: level {} -> {}, next {}", id, card.level, level, next),
+        Err(e) => fail!("Error updating card: {}", e),
+    }
+}
+
+fn get_sequence(conn: &Connection, deck_id: i32) -> Result<Vec<i32>> {
+    debug!("get_sequence: deck_id={}", deck_id);
+    let mut stmt = conn.prepare("SELECT id FROM flashcards WHERE deck_id = ?1 ORDER BY order_index, id")?;
+    let ids = stmt.query_map(params![deck_id], |row| row.get(0))?.collect();
+    ids
+}
+
+fn update_sequence(conn: &Connection, sequence: &[i32]) -> Result<()> {
+    debug!("update_sequence: {} cards", sequence.len());
+    let tx = Savepoint::new(conn, "reorder")?;
+    let mut stmt = tx.prepare("UPDATE flashcards SET order_index = ?1 WHERE id = ?2")?;
+    for (i, id) in sequence.iter().enumerate() {
+        stmt.execute(params![i as i32 + 1, id])?;
+    }
+    drop(stmt);
+    tx.commit()
+}
+
+// Card ids, one per line; `#` starts a comment and blank lines are skipped.
+fn parse_ids(text: &str) -> std::result::Result<Vec<i32>, String> {
+    text.lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.parse().map_err(|_| format!("Invalid card id: {}", line)))
+        .collect()
+}
+
+/// `reorder <deck_name> [<file>|-]`: sets the fixed sequence `quiz --quiz-order
+/// sequence` and `list cards --sort sequence` follow.
+///
+/// The file (or stdin, for `-`) lists card ids one per line. Those cards come
+/// first, in that order, and the rest keep their current order after them.
+/// Without a file the current sequence is printed, ready to edit and feed back.
+/// New cards are added at the end of the sequence.
+pub fn reorder(conn: &Connection, args: &[String]) {
+    if args.len() < 3 {
+        fail!("Missing <deck_name>");
+        return;
+    }
+
+    let deck_id = match get_deck_id(conn, &args[2]) {
+        Ok(id) => id,
+        Err(e) => {
+            fail!("Error getting deck id: {}", e);
+            return;
+        }
+    };
+
+    let current = match get_sequence(conn, deck_id) {
+        Ok(ids) => ids,
+        Err(e) => {
+            fail!("Error reading flashcards: {}", e);
+            return;
+        }
+    };
+
+    let path = match args.get(3) {
+        Some(path) => path,
+        None => {
+            for card in current.iter().filter_map(|&id| get_flashcard(conn, id).ok()) {
+                println!("{}  # {}", card.id, first_line(&card.front));
+            }
+            return;
+        }
+    };
+
+    let text = if path == "-" {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text).map(|_| text)
+    } else {
+        read_to_string(path)
+    };
+    let text = match text {
+        Ok(text) => text,
+        Err(e) => {
+            fail!("Error reading {}: {}", path, e);
+            return;
+        }
+    };
+
+    let sequence = match parse_ids(&text).and_then(|listed| resequence(&current, &listed)) {
+        Ok(sequence) => sequence,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    match update_sequence(conn, &sequence) {
+        Ok(_) => println!("Reordered {} cards in deck {}", sequence.len(), args[2]),
+        Err(e) => fail!("Error updating sequence: {}", e),
+    }
+}
+
+/// Deletes a card along with everything recorded about it, so no orphaned
+/// log rows are left behind.
+fn delete_card_rows(conn: &Connection, id: i32) -> Result<usize> {
+    debug!("delete_card_rows: id={}", id);
+    conn.execute("DELETE FROM flashcard_log WHERE question_id = ?1", params![id])?;
+    conn.execute("DELETE FROM typos WHERE card_id = ?1", params![id])?;
+    conn.execute("DELETE FROM card_tags WHERE card_id = ?1", params![id])?;
+    conn.execute("DELETE FROM journal WHERE card_id = ?1", params![id])?;
+    conn.execute("DELETE FROM card_fields WHERE card_id = ?1", params![id])?;
+    conn.execute("DELETE FROM card_events WHERE card_id = ?1", params![id])?;
+    conn.execute("DELETE FROM flashcards WHERE id = ?1", params![id])
+}
+
+fn delete_deck_rows(conn: &Connection, deck_id: i32, cards: &[Flashcard]) -> Result<()> {
+    debug!("delete_deck_rows: deck_id={} cards={}", deck_id, cards.len());
+    let tx = Savepoint::new(conn, "delete_deck")?;
+    for card in cards {
+        delete_card_rows(&tx, card.id)?;
+    }
+    tx.execute("DELETE FROM decks WHERE id = ?1", params![deck_id])?;
+    tx.commit()
+}
+
+/// `delete deck <deck_name> [--yes]`: removes a deck with all its cards and
+/// their review history, after confirmation.
+pub fn delete_deck(conn: &Connection, args: &[String]) {
+    let mut args = args.to_vec();
+    let yes = take_flag(&mut args, "--yes");
+
+    if args.len() < 4 {
+        fail!("Missing <deck_name>");
+        return;
+    }
+
+    // Also checks that a deck given by id exists.
+    let (deck_id, name) = match get_deck_id(conn, &args[3]).and_then(|id| Ok((id, get_deck_name(conn, id)?))) {
+        Ok(deck) => deck,
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            fail!("No deck with name or id {}", args[3]);
+            return;
+        },
+        Err(e) => {
+            fail!("Error getting deck id: {}", e);
+            return;
+        }
+    };
+
+    let cards = match get_flashcards(conn, deck_id) {
+        Ok(cards) => cards,
+        Err(e) => {
+            fail!("Error reading flashcards: {}", e);
+            return;
+        }
+    };
+
+    let summary = format!(
+        "This will delete deck {} and its {} cards, with their review history:\n{}",
+        name,
+        cards.len(),
+        examples(&card_lines(&cards), cards.len()),
+    );
+    if !confirm(summary.trim_end(), yes) {
+        return;
+    }
+
+    match delete_deck_rows(conn, deck_id, &cards) {
+        Ok(_) => {
+            println!("Deleted deck {} and {} cards", name, cards.len());
+        },
+        Err(e) => {
+            fail!("Error deleting deck: {}", e);
+        }
+    }
+}
+
+/// `delete card <card_id>`: removes one card and its review history.
+pub fn delete_card(conn: &Connection, args: &[String]) {
+    if args.len() < 4 {
+        fail!("Missing <card_id>");
+        return;
+    }
+
+    let id: i32 = match args[3].parse() {
+        Ok(id) => id,
+        Err(_) => {
+            fail!("Invalid card id: {}", args[3]);
+            return;
+        }
+    };
+
+    match delete_card_rows(conn, id) {
+        Ok(0) => {
+            fail!("No card with id {}", id);
+        },
+        Ok(_) => {
+            println!("Card deleted: {}", id);
+        },
+        Err(e) => {
+            fail!("Error deleting card: {}", e);
+        }
+    }
+}
+
+/// What `trim` ranks a deck's cards by; the lowest-ranked are removed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TrimBy {
+    // Most reviews first.
+    Reviews,
+    // Highest share of correct reviews first; unreviewed cards count as 0.
+    Accuracy,
+    // Fewest lapses first.
+    Lapses,
+}
+
+impl TrimBy {
+    fn parse(name: &str) -> Option<TrimBy> {
+        match name {
+            "reviews" => Some(TrimBy::Reviews),
+            "accuracy" => Some(TrimBy::Accuracy),
+            "lapses" => Some(TrimBy::Lapses),
+            _ => None,
+        }
+    }
+}
+
+// A card as `trim` ranks it, from the denormalized review counters.
+struct TrimCard {
+    id: i32,
+    front: String,
+    review_count: i32,
+    lapse_count: i32,
+}
+
+impl TrimCard {
+    fn accuracy(&self) -> f64 {
+        (self.review_count - self.lapse_count) as f64 / self.review_count.max(1) as f64
+    }
+
+    fn line(&self) -> String {
+        format!("{}: {} ({} reviews, {} lapses)", self.id, first_line(&self.front), self.review_count, self.lapse_count)
+    }
+}
+
+fn get_trim_cards(conn: &Connection, deck_id: i32) -> Result<Vec<TrimCard>> {
+    debug!("get_trim_cards: deck_id={}", deck_id);
+    let mut stmt = conn.prepare("SELECT id, front, review_count, lapse_count FROM flashcards WHERE deck_id = ?1 ORDER BY id")?;
+    let cards = stmt.query_map(params![deck_id], |row| {
+        Ok(TrimCard { id: row.get(0)?, front: row.get(1)?, review_count: row.get(2)?, lapse_count: row.get(3)? })
+    })?.collect();
+    cards
+}
+
+fn delete_trimmed_rows(conn: &Connection, cards: &[TrimCard]) -> Result<()> {
+    debug!("delete_trimmed_rows: cards={}", cards.len());
+    let tx = Savepoint::new(conn, "trim")?;
+    for card in cards {
+        delete_card_rows(&tx, card.id)?;
+    }
+    tx.commit()
+}
+
+// Sorts the cards to keep first. Ties go to the most reviewed card and then
+// the oldest, so the order is the same on every run.
+fn rank_for_trim(cards: &mut [TrimCard], by: TrimBy) {
+    cards.sort_by(|a, b| {
+        let first = match by {
+            TrimBy::Reviews => std::cmp::Ordering::Equal,
+            TrimBy::Accuracy => b.accuracy().total_cmp(&a.accuracy()),
+            TrimBy::Lapses => a.lapse_count.cmp(&b.lapse_count),
+        };
+        first.then(b.review_count.cmp(&a.review_count)).then(a.id.cmp(&b.id))
+    });
+}
+
+/// `trim <deck_name> --keep N [--by reviews|accuracy|lapses] [--yes]`: keeps
+/// the deck's N best cards and deletes the rest with their review history,
+/// after confirmation.
+///
+/// Cards are ranked by the review counters (see `rebuild`), by accuracy
+/// unless `--by` says otherwise, so each card's standing is only as good as
+/// its review history. `--dry-run` lists what would be removed.
+pub fn trim(conn: &Connection, args: &[String]) {
+    let mut args = args.to_vec();
+    let yes = take_flag(&mut args, "--yes");
+
+    let keep: usize = match take_parsed(&mut args, "--keep") {
+        Ok(Some(keep)) => keep,
+        Ok(None) => {
+            fail!("Missing --keep <N>");
+            return;
+        },
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    let by = match take_value(&mut args, "--by") {
+        Ok(Some(name)) => match TrimBy::parse(&name) {
+            Some(by) => by,
+            None => {
+                fail!("Unknown --by: {} (supported: reviews, accuracy, lapses)", name);
+                return;
+            }
+        },
+        Ok(None) => TrimBy::Accuracy,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    if args.len() < 3 {
+        fail!("Missing <deck_name>");
+        return;
+    }
+
+    let deck_id = match get_deck_id(conn, &args[2]) {
+        Ok(id) => id,
+        Err(e) => {
+            fail!("Error getting deck id: {}", e);
+            return;
+        }
+    };
+
+    let mut cards = match get_trim_cards(conn, deck_id) {
+        Ok(cards) => cards,
+        Err(e) => {
+            fail!("Error reading flashcards: {}", e);
+            return;
+        }
+    };
+
+    if cards.len() <= keep {
+        println!("Deck {} has {} cards; nothing to trim", args[2], cards.len());
+        return;
+    }
+
+    rank_for_trim(&mut cards, by);
+    let removed = cards.split_off(keep);
+    let lines: Vec<String> = removed.iter().map(TrimCard::line).collect();
+
+    let summary = format!(
+        "This will delete {} of the {} cards in deck {}, with their review history, keeping {}:\n{}",
+        removed.len(),
+        removed.len() + keep,
+        args[2],
+        keep,
+        examples(&lines, lines.len()),
+    );
+    if !confirm(summary.trim_end(), yes) {
+        return;
+    }
+
+    match delete_trimmed_rows(conn, &removed) {
+        Ok(_) => {
+            println!("Removed {} cards from deck {}:", removed.len(), args[2]);
+            for line in &lines {
+                println!("  {}", line);
+            }
+        },
+        Err(e) => {
+            fail!("Error trimming deck: {}", e);
+        }
+    }
+}
+
+// Cards that count as duplicates when merging: the same front by `match_on`,
+// and the same back, compared exactly with `--match-on front` and the way
+// typed answers are otherwise. Note-type cards are rendered from their fields
+// and are never treated as duplicates.
+fn dedup_key(card: &Flashcard, match_on: MatchOn) -> Option<(String, String)> {
+    match (card.note_type_id, match_on) {
+        (Some(_), _) => None,
+        (None, MatchOn::Front) => Some((card.front.clone(), card.back.clone())),
+        (None, MatchOn::Hash) => Some((match_on.key(&card.front), answer::comparable(&card.back))),
+    }
+}
+
+/// Pairs each `source` card that duplicates a `target` card with the id of
+/// the target card it duplicates.
+fn find_duplicates(source: &[Flashcard], target: &[Flashcard], match_on: MatchOn) -> Vec<(i32, i32)> {
+    let survivors: HashMap<(String, String), i32> = target.iter()
+        .filter_map(|card| Some((dedup_key(card, match_on)?, card.id)))
+        .collect();
+    source.iter()
+        .filter_map(|card| Some((card.id, *survivors.get(&dedup_key(card, match_on)?)?)))
+        .collect()
+}
+
+fn merge_decks(conn: &Connection, source_id: i32, target_id: i32, duplicates: &[(i32, i32)], keep_log: bool) -> Result<usize> {
+    debug!("merge_decks: {} -> {} duplicates={} keep_log={}", source_id, target_id, duplicates.len(), keep_log);
+    let tx = Savepoint::new(conn, "merge")?;
+
+    for (loser, survivor) in duplicates {
+        if keep_log {
+            tx.execute("UPDATE flashcard_log SET question_id = ?1 WHERE question_id = ?2", params![survivor, loser])?;
+            tx.execute("UPDATE typos SET card_id = ?1 WHERE card_id = ?2", params![survivor, loser])?;
+            tx.execute("UPDATE card_events SET card_id = ?1 WHERE card_id = ?2", params![survivor, loser])?;
+        }
+        delete_card_rows(&tx, *loser)?;
+    }
+    if keep_log && !duplicates.is_empty() {
+        rebuild_counters(&tx)?;
+    }
+
+    // Moved cards keep their relative sequence, after the target's own cards.
+    let moved = tx.execute(
+        "UPDATE flashcards SET deck_id = ?1,
+            order_index = order_index + (SELECT COALESCE(MAX(order_index), 0) FROM flashcards WHERE deck_id = ?1)
+        WHERE deck_id = ?2",
+        params![target_id, source_id],
+    )?;
+    tx.execute("DELETE FROM decks WHERE id = ?1", params![source_id])?;
+
+    tx.commit()?;
+    Ok(moved)
+}
+
+/// `merge <source_deck> <target_deck> [--dedup] [--match-on front|hash] [--merge-log-on-move] [--yes]`
+///
+/// Moves every card of the source deck into the target deck and removes the
+/// source deck. A card's review history follows it, since the log is keyed by
+/// card id.
+///
+/// With `--dedup`, source cards that duplicate a target card are deleted
+/// instead of moved, and their review history is deleted with them. Fronts
+/// match as `identity` describes; `--match-on front` also compares backs
+/// exactly. Add `--merge-log-on-move` to re-point that history (and any captured typos) to
+/// the surviving card instead; its review counters are recomputed to match.
+pub fn merge(conn: &Connection, args: &[String]) {
+    let mut args = args.to_vec();
+    let dedup = take_flag(&mut args, "--dedup");
+    let keep_log = take_flag(&mut args, "--merge-log-on-move");
+    let yes = take_flag(&mut args, "--yes");
+    let match_on = match take_match_on(&mut args) {
+        Ok(match_on) => match_on,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    if args.len() < 3 {
+        fail!("Missing <source_deck>");
+        return;
+    }
+    if args.len() < 4 {
+        fail!("Missing <target_deck>");
+        return;
+    }
+    if keep_log && !dedup {
+        fail!("--merge-log-on-move only applies with --dedup");
+        return;
+    }
+
+    let (source_id, target_id) = match (get_deck_id(conn, &args[2]), get_deck_id(conn, &args[3])) {
+        (Ok(source_id), Ok(target_id)) => (source_id, target_id),
+        (Err(e), _) | (_, Err(e)) => {
+            fail!("Error getting deck id: {}", e);
+            return;
+        }
+    };
+    if source_id == target_id {
+        fail!("Can't merge deck {} into itself", args[2]);
+        return;
+    }
+
+    let (source, target) = match (get_flashcards(conn, source_id), get_flashcards(conn, target_id)) {
+        (Ok(source), Ok(target)) => (source, target),
+        (Err(e), _) | (_, Err(e)) => {
+            fail!("Error reading flashcards: {}", e);
+            return;
+        }
+    };
+
+    let duplicates = if dedup { find_duplicates(&source, &target, match_on) } else { Vec::new() };
+    let lines: Vec<String> = duplicates.iter()
+        .filter_map(|(loser, survivor)| Some(format!("{} duplicates {}: {}", loser, survivor, first_line(&source.iter().find(|card| card.id == *loser)?.front))))
+        .collect();
+    let mut summary = format!(
+        "This will move {} cards from deck {} into deck {} and remove deck {}",
+        source.len() - duplicates.len(),
+        args[2],
+        args[3],
+        args[2],
+    );
+    if !duplicates.is_empty() {
+        summary.push_str(&format!(
+            ",\nand delete {} duplicate cards, {} their review history:\n{}",
+            duplicates.len(),
+            if keep_log { "keeping" } else { "along with" },
+            examples(&lines, lines.len()),
+        ));
+    }
+    if !confirm(summary.trim_end(), yes) {
+        return;
+    }
+
+    match merge_decks(conn, source_id, target_id, &duplicates, keep_log) {
+        Ok(moved) => {
+            println!("Merged deck {} into {}: {} moved, {} duplicates removed", args[2], args[3], moved, duplicates.len());
+        },
+        Err(e) => {
+            fail!("Error merging decks: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn card(id: i32, front: &str, back: &str) -> Flashcard {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        Flashcard {
+            id,
+            deck_id: 1,
+            front: front.to_string(),
+            back: back.to_string(),
+            added: date,
+            next: date,
+            level: 1,
+            ease_factor: crate::scheduler::INITIAL_EASE,
+            repetitions: 0,
+            interval_days: 0,
+            note_type_id: None,
+            source: None,
+            suspended: false,
+            tags: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn trim_ranks_by_each_counter() {
+        let cards = || vec![(1, 4, 2), (2, 10, 5), (3, 0, 0), (4, 5, 0), (5, 2, 0)].into_iter()
+            .map(|(id, review_count, lapse_count)| TrimCard { id, front: String::new(), review_count, lapse_count })
+            .collect::<Vec<_>>();
+        let ranked = |by| {
+            let mut cards = cards();
+            rank_for_trim(&mut cards, by);
+            cards.iter().map(|card| card.id).collect::<Vec<_>>()
+        };
+        assert_eq!(ranked(TrimBy::Reviews), vec![2, 4, 1, 5, 3]);
+        assert_eq!(ranked(TrimBy::Accuracy), vec![4, 5, 2, 1, 3]);
+        assert_eq!(ranked(TrimBy::Lapses), vec![4, 5, 3, 1, 2]);
+    }
+
+    #[test]
+    fn promotion_never_lowers_a_level() {
+        assert_eq!(adjusted_level(3, 1), Some(4));
+        assert_eq!(adjusted_level(MAX_LEVEL, 1), None);
+        assert_eq!(adjusted_level(MAX_LEVEL + 1, 1), None);
+        assert_eq!(adjusted_level(MAX_LEVEL + 1, -1), Some(MAX_LEVEL));
+        assert_eq!(adjusted_level(1, -1), None);
+    }
+
+    #[test]
+    fn level_changes_are_recorded_as_events() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::create_tables(&conn).unwrap();
+        conn.execute("INSERT INTO decks (id, name) VALUES (1, 'Geo')", []).unwrap();
+        conn.execute("INSERT INTO flashcards (id, deck_id, front, back, level) VALUES (1, 1, 'Q', 'A', 2)", []).unwrap();
+
+        update_level(&conn, 1, 2, 3, &NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()).unwrap();
+        let event: (String, String) = conn.query_row("SELECT event, detail FROM card_events WHERE card_id = 1", [], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+        assert_eq!(event, ("Promote".to_string(), "level 2 -> 3".to_string()));
+    }
+
+    #[test]
+    fn deleting_a_note_type_card_removes_its_fields() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::create_tables(&conn).unwrap();
+        conn.execute("INSERT INTO decks (id, name) VALUES (1, 'Geo')", []).unwrap();
+        conn.execute("INSERT INTO note_types (id, name, fields) VALUES (1, 'Capital', 'country,capital')", []).unwrap();
+        conn.execute("INSERT INTO flashcards (id, deck_id, front, back, level, note_type_id) VALUES (1, 1, '', '', 1, 1)", []).unwrap();
+        conn.execute("INSERT INTO card_fields (card_id, name, value) VALUES (1, 'country', 'France'), (1, 'capital', 'Paris')", []).unwrap();
+
+        assert_eq!(delete_card_rows(&conn, 1).unwrap(), 1);
+        let fields: i32 = conn.query_row("SELECT COUNT(*) FROM card_fields", [], |row| row.get(0)).unwrap();
+        assert_eq!(fields, 0);
+    }
+
+    #[test]
+    fn duplicates_match_on_comparable_front_and_back() {
+        let source = vec![card(1, "Capital of France?", "Paris"), card(2, "capital of  france?", "paris"), card(3, "Capital of Spain?", "Madrid")];
+        let target = vec![card(10, "Capital of France?", "Paris"), card(11, "Capital of Spain?", "Barcelona")];
+        assert_eq!(find_duplicates(&source, &target, MatchOn::Hash), vec![(1, 10), (2, 10)]);
+        assert_eq!(find_duplicates(&source, &target, MatchOn::Front), vec![(1, 10)]);
+    }
+}
@@ -0,0 +1,206 @@
+// Orderings for the cards of a quiz session.
+
+use std::collections::VecDeque;
+
+use rand::Rng;
+
+/// How the cards of a session are ordered.
+#[derive(Clone, Copy, PartialEq)]
+pub enum QuizOrder {
+    Shuffle,
+    // Shuffle, then spread cards sharing a topic across the session.
+    Spaced,
+    // Hardest cards first, by difficulty score.
+    Difficulty,
+    // The deck's own sequence, as set by `reorder`.
+    Sequence,
+}
+
+impl QuizOrder {
+    pub fn parse(name: &str) -> Option<QuizOrder> {
+        match name {
+            "shuffle" => Some(QuizOrder::Shuffle),
+            "spaced" => Some(QuizOrder::Spaced),
+            "difficulty" => Some(QuizOrder::Difficulty),
+            "sequence" => Some(QuizOrder::Sequence),
+            _ => None,
+        }
+    }
+}
+
+/// Reorders `cards` so that consecutive cards don't share a topic, where possible.
+///
+/// This is best-effort: each step takes the next card from the largest
+/// remaining topic other than the previous card's, and only repeats a topic
+/// when nothing else is left. Cards without a topic never conflict. Within a
+/// topic the incoming (shuffled) order is kept.
+pub fn interleave<T, F>(cards: Vec<T>, topic: F) -> Vec<T>
+where
+    F: Fn(&T) -> Option<String>,
+{
+    let total = cards.len();
+    let mut buckets: Vec<(Option<String>, VecDeque<T>)> = Vec::new();
+    for card in cards {
+        let key = topic(&card);
+        match buckets.iter_mut().find(|(t, _)| *t == key) {
+            Some((_, bucket)) => bucket.push_back(card),
+            None => buckets.push((key, VecDeque::from([card]))),
+        }
+    }
+
+    let mut result = Vec::with_capacity(total);
+    let mut last: Option<String> = None;
+
+    while !buckets.is_empty() {
+        let eligible = |t: &Option<String>| t.is_none() || *t != last;
+        let pick = buckets.iter()
+            .enumerate()
+            .filter(|(_, (t, _))| eligible(t))
+            // Largest bucket first; ties go to the earlier bucket.
+            .max_by(|(i, (_, a)), (j, (_, b))| a.len().cmp(&b.len()).then(j.cmp(i)))
+            .map(|(i, _)| i)
+            // Only the previous topic is left: repeat it.
+            .unwrap_or(0);
+
+        let (key, bucket) = &mut buckets[pick];
+        result.push(bucket.pop_front().unwrap());
+        last = key.clone();
+
+        if bucket.is_empty() {
+            buckets.remove(pick);
+        }
+    }
+
+    result
+}
+
+/// The new sequence of a deck's cards: the `listed` ids first, in the given
+/// order, then every other card in its `current` order.
+///
+/// Fails on ids that aren't in `current` or are listed twice.
+pub fn resequence(current: &[i32], listed: &[i32]) -> Result<Vec<i32>, String> {
+    let mut sequence = Vec::with_capacity(current.len());
+    for id in listed {
+        if !current.contains(id) {
+            return Err(format!("Card {} is not in this deck", id));
+        }
+        if sequence.contains(id) {
+            return Err(format!("Card {} is listed twice", id));
+        }
+        sequence.push(*id);
+    }
+    sequence.extend(current.iter().filter(|id| !listed.contains(id)));
+    Ok(sequence)
+}
+
+/// Moves the `n` highest-level cards to the front, highest first, so a
+/// session opens with cards that are easy to recall. The rest keep their
+/// order, and equal levels keep theirs.
+pub fn warm_up<T, F>(cards: Vec<T>, n: usize, level: F) -> Vec<T>
+where
+    F: Fn(&T) -> i32,
+{
+    let mut by_level: Vec<usize> = (0..cards.len()).collect();
+    by_level.sort_by_key(|&i| std::cmp::Reverse(level(&cards[i])));
+    let mut picked = vec![false; cards.len()];
+    for &i in by_level.iter().take(n) {
+        picked[i] = true;
+    }
+
+    let mut cards: Vec<Option<T>> = cards.into_iter().map(Some).collect();
+    let mut result: Vec<T> = by_level.iter().take(n).map(|&i| cards[i].take().unwrap()).collect();
+    result.extend(cards.into_iter().zip(picked).filter(|(_, picked)| !picked).filter_map(|(card, _)| card));
+    result
+}
+
+/// Shuffles `cards` so that heavier cards tend to come first.
+///
+/// A weighted random permutation (Efraimidis-Spirakis): each card draws the
+/// key `u^(1/weight)` for a uniform `u`, and cards are sorted by key, largest
+/// first. Each next card is picked with probability proportional to its weight
+/// among those remaining, and equal weights give a uniform shuffle. Weights
+/// below 1 count as 1.
+pub fn weighted_shuffle<T, F, R>(cards: Vec<T>, weight: F, rng: &mut R) -> Vec<T>
+where
+    F: Fn(&T) -> u32,
+    R: Rng,
+{
+    let mut keyed: Vec<(f64, T)> = cards.into_iter()
+        .map(|card| {
+            let w = weight(&card).max(1) as f64;
+            (rng.gen::<f64>().powf(1.0 / w), card)
+        })
+        .collect();
+    keyed.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    keyed.into_iter().map(|(_, card)| card).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn topics(cards: &[(i32, Option<&str>)]) -> Vec<Option<String>> {
+        cards.iter().map(|(_, t)| t.map(|t| t.to_string())).collect()
+    }
+
+    #[test]
+    fn warm_up_fronts_the_highest_levels() {
+        let cards = vec![(1, 2), (2, 5), (3, 1), (4, 5), (5, 3)];
+        let ordered = warm_up(cards.clone(), 3, |(_, level)| *level);
+        let ids: Vec<i32> = ordered.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, vec![2, 4, 5, 1, 3]);
+        assert_eq!(warm_up(cards.clone(), 0, |(_, level)| *level), cards);
+        assert_eq!(warm_up(cards.clone(), 10, |(_, level)| *level).len(), 5);
+    }
+
+    #[test]
+    fn interleave_separates_topics() {
+        let cards = vec![(1, Some("a")), (2, Some("a")), (3, Some("b")), (4, Some("b")), (5, Some("a"))];
+        let ordered = interleave(cards, |(_, t)| t.map(|t| t.to_string()));
+        let topics = topics(&ordered);
+        for pair in topics.windows(2) {
+            assert_ne!(pair[0], pair[1]);
+        }
+        assert_eq!(ordered.len(), 5);
+    }
+
+    #[test]
+    fn interleave_repeats_when_unavoidable() {
+        let cards = vec![(1, Some("a")), (2, Some("a")), (3, None)];
+        let ordered = interleave(cards, |(_, t)| t.map(|t| t.to_string()));
+        assert_eq!(ordered.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![1, 3, 2]);
+    }
+
+    #[test]
+    fn resequence_puts_listed_cards_first() {
+        assert_eq!(resequence(&[1, 2, 3, 4], &[3, 1]), Ok(vec![3, 1, 2, 4]));
+        assert!(resequence(&[1, 2], &[5]).is_err());
+        assert!(resequence(&[1, 2], &[2, 2]).is_err());
+    }
+
+    #[test]
+    fn weighted_shuffle_favors_heavier_cards() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut heavy_first = 0;
+        for _ in 0..1000 {
+            let cards = vec![("light", 1), ("heavy", 9)];
+            let ordered = weighted_shuffle(cards, |(_, w)| *w, &mut rng);
+            if ordered[0].0 == "heavy" {
+                heavy_first += 1;
+            }
+        }
+        // Expected 900 of 1000.
+        assert!((850..950).contains(&heavy_first), "heavy first {} times", heavy_first);
+    }
+
+    #[test]
+    fn weighted_shuffle_keeps_every_card() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut ordered = weighted_shuffle((0..20).collect(), |_| 1, &mut rng);
+        ordered.sort();
+        assert_eq!(ordered, (0..20).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,435 @@
+// Parsing of card files as imported by `add cards`.
+//
+// Each format (see `format`) reads its files into `RawCard`s; `parse_cards`
+// then turns those into flashcards the same way for every format.
+//
+// In flash's own format, a card block may start with metadata lines of the
+// form `key: value` before its front side:
+//
+//     added: 2024-01-10
+//     tags: geography, capitals
+//     What is the capital of France?
+//     <>
+//     Paris
+//
+// Metadata lines only count before the front, and an `added:` line only if
+// its date parses; a `tags:` line anywhere else is an error rather than card
+// text.
+//
+// Separators (`----` between cards, `<>` between sides) only count on a line
+// of their own, so they can appear inside a card's text. A line of card text
+// that is just a separator, or that starts like a metadata line, is escaped
+// with a backslash, as `\----`, `\<>` or `\tags:`; see `escape`.
+
+use chrono::NaiveDate;
+use log::warn;
+
+use crate::scheduler::{Review, INITIAL_EASE};
+use crate::json::Value;
+use crate::tags::parse_tags;
+use crate::Flashcard;
+
+/// A problem found in a card block: either why it couldn't be turned into a
+/// flashcard, or a warning about a card that was accepted anyway.
+pub struct ParseError {
+    // 1-based position of the block among the file's cards.
+    pub block: usize,
+    pub message: String,
+}
+
+/// What to do with a card whose front or back is empty once trimmed; set
+/// with the `empty_sides` setting.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EmptySides {
+    Reject,
+    Warn,
+}
+
+impl EmptySides {
+    pub fn parse(name: &str) -> Option<EmptySides> {
+        match name {
+            "reject" => Some(EmptySides::Reject),
+            "warn" => Some(EmptySides::Warn),
+            _ => None,
+        }
+    }
+}
+
+/// Describes what's wrong with a card whose front or back is blank, if anything.
+///
+/// Shared by everything that creates or changes cards, so they agree on what
+/// counts as empty.
+pub fn check_sides(front: &str, back: &str) -> Option<String> {
+    match (front.trim().is_empty(), back.trim().is_empty()) {
+        (true, true) => Some("empty front and back".to_string()),
+        (true, false) => Some("empty front".to_string()),
+        (false, true) => Some("empty back".to_string()),
+        (false, false) => None,
+    }
+}
+
+/// The cards parsed from a file, the blocks that were rejected, and the
+/// warnings about cards that were kept.
+#[derive(Default)]
+pub struct Parsed {
+    pub cards: Vec<Flashcard>,
+    pub errors: Vec<ParseError>,
+    pub warnings: Vec<ParseError>,
+}
+
+/// A card as read from a file, before it belongs to a deck.
+pub struct RawCard {
+    pub front: String,
+    pub back: String,
+    pub added: Option<NaiveDate>,
+    pub tags: Vec<String>,
+    // Review state carried by formats that export it, kept on import.
+    pub schedule: Option<Review>,
+}
+
+/// A card read from a file, numbered by its 1-based position among the
+/// file's cards, or why it couldn't be read.
+pub type Block = (usize, Result<RawCard, String>);
+
+#[derive(Default)]
+struct CardMeta {
+    added: Option<NaiveDate>,
+    tags: Vec<String>,
+}
+
+// The metadata key a line starts with, if it's one `split_meta` knows.
+fn meta_key(line: &str) -> Option<&str> {
+    match line.trim().split_once(':') {
+        Some((key @ ("added" | "tags"), _)) => Some(key),
+        _ => None,
+    }
+}
+
+// Splits the leading metadata lines off a card block. An `added:` line whose
+// date doesn't parse is left as the start of the front.
+fn split_meta(block: &str) -> (CardMeta, &str) {
+    let mut meta = CardMeta::default();
+    let mut offset = 0;
+
+    for line in block.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            offset += line.len();
+            continue;
+        }
+
+        match trimmed.split_once(':') {
+            Some(("added", value)) => match NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d") {
+                Ok(date) => {
+                    meta.added = Some(date);
+                    offset += line.len();
+                },
+                Err(_) => {
+                    warn!("reading invalid added date as card text: {}", value.trim());
+                    break;
+                }
+            },
+            Some(("tags", value)) => {
+                meta.tags = parse_tags(value);
+                offset += line.len();
+            },
+            _ => break,
+        }
+    }
+
+    (meta, &block[offset..])
+}
+
+pub const CARD_SEPARATOR: &str = "----";
+pub const SIDE_SEPARATOR: &str = "<>";
+
+/// Splits `text` at the lines holding nothing but `separator`.
+pub fn split_at_lines<'a>(text: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let (mut start, mut offset) = (0, 0);
+
+    for line in text.split_inclusive('\n') {
+        if line.trim() == separator {
+            parts.push(&text[start..offset]);
+            start = offset + line.len();
+        }
+        offset += line.len();
+    }
+    parts.push(&text[start..]);
+
+    parts
+}
+
+// A line that would read as a separator or a metadata line, or as an escaped
+// one: backslashes followed by `----`, `<>`, `added:` or `tags:`. Returns
+// where the separator or key starts.
+fn escapable(line: &str) -> Option<usize> {
+    let trimmed = line.trim();
+    let unescaped = trimmed.trim_start_matches('\\');
+    if unescaped != CARD_SEPARATOR && unescaped != SIDE_SEPARATOR && meta_key(unescaped).is_none() {
+        return None;
+    }
+    Some(line.len() - line.trim_start().len())
+}
+
+/// Backslash-escapes the lines of a card side that would otherwise read as
+/// separators or metadata, so `unescape` gives the side back unchanged.
+pub fn escape(side: &str) -> String {
+    side.split_inclusive('\n')
+        .map(|line| match escapable(line) {
+            Some(at) => format!("{}\\{}", &line[..at], &line[at..]),
+            None => line.to_string(),
+        })
+        .collect()
+}
+
+fn unescape(side: &str) -> String {
+    side.split_inclusive('\n')
+        .map(|line| match escapable(line) {
+            Some(at) if line[at..].starts_with('\\') => format!("{}{}", &line[..at], &line[at + 1..]),
+            _ => line.to_string(),
+        })
+        .collect()
+}
+
+/// Reads the blocks of a flash card file (without the deck header) into
+/// cards. Each side is trimmed.
+pub fn parse_blocks(blocks: &[&str]) -> Vec<Block> {
+    let mut cards = Vec::new();
+
+    for (i, block) in blocks.iter().enumerate() {
+        let (meta, card) = split_meta(block);
+        let sides = split_at_lines(card, SIDE_SEPARATOR);
+
+        if sides.len() != 2 {
+            // The file usually ends with a separator; that empty tail isn't a card.
+            if !card.trim().is_empty() {
+                cards.push((i + 1, Err(format!("expected 2 sides separated by a {} line, found {}", SIDE_SEPARATOR, sides.len()))));
+            }
+            continue;
+        }
+
+        if card.lines().any(|line| meta_key(line) == Some("tags")) {
+            cards.push((i + 1, Err("tags: line after the front; move it above the front, or write \\tags: to keep it as text".to_string())));
+            continue;
+        }
+
+        cards.push((i + 1, Ok(RawCard {
+            front: unescape(sides[0].trim()),
+            back: unescape(sides[1].trim()),
+            added: meta.added,
+            tags: meta.tags,
+            schedule: None,
+        })));
+    }
+
+    cards
+}
+
+// A date field of a json card, if it's there.
+fn json_date(card: &Value, field: &str) -> Result<Option<NaiveDate>, String> {
+    match card.get(field) {
+        None => Ok(None),
+        Some(value) => value.as_str()
+            .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+            .map(Some)
+            .ok_or_else(|| format!("invalid {}: expected a YYYY-MM-DD date", field)),
+    }
+}
+
+// A whole-number field of a json card, if it's there.
+fn json_int(card: &Value, field: &str) -> Result<Option<i32>, String> {
+    match card.get(field) {
+        None => Ok(None),
+        Some(value) => value.as_f64()
+            .filter(|n| n.fract() == 0.0 && n.abs() <= i32::MAX as f64)
+            .map(|n| Some(n as i32))
+            .ok_or_else(|| format!("invalid {}: expected a whole number", field)),
+    }
+}
+
+/// Reads one card of the json format (see `export::to_json`). Only `front`
+/// and `back` are required; review state is read when `level` and `next` are
+/// there, with SM-2 fields that are missing starting out as for a new card.
+pub fn json_card(card: &Value) -> Result<RawCard, String> {
+    let side = |name: &str| card.get(name)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| format!("missing {}", name));
+    let (front, back) = (side("front")?, side("back")?);
+
+    let tags = match card.get("tags") {
+        None => Vec::new(),
+        Some(tags) => {
+            let tags: Option<Vec<&str>> = tags.as_array()
+                .map(|tags| tags.iter().map(Value::as_str).collect())
+                .unwrap_or(None);
+            parse_tags(&tags.ok_or("invalid tags: expected a list of strings")?.join(" "))
+        },
+    };
+
+    let schedule = match (json_int(card, "level")?, json_date(card, "next")?) {
+        (None, None) => None,
+        (Some(level), Some(next)) => Some(Review {
+            level,
+            next,
+            ease_factor: match card.get("ease_factor") {
+                None => INITIAL_EASE,
+                Some(ease) => ease.as_f64().ok_or("invalid ease_factor: expected a number")?,
+            },
+            repetitions: json_int(card, "repetitions")?.unwrap_or(0),
+            interval_days: json_int(card, "interval_days")?.unwrap_or(0),
+        }),
+        _ => return Err("level and next must be given together".to_string()),
+    };
+
+    Ok(RawCard { front, back, added: json_date(card, "added")?, tags, schedule })
+}
+
+/// Turns cards read from a file into flashcards for `deck_id`, along with the
+/// cards that couldn't be read or were rejected.
+///
+/// Cards are stamped with `today`, unless `preserve_added` is set and the
+/// card carries its own `added:` date, and remember `source` as their origin.
+/// They start out new unless the format carried their review state.
+/// Cards with an empty side are rejected or kept with a warning, per `empty_sides`.
+pub fn parse_cards(deck_id: i32, cards: Vec<Block>, today: &NaiveDate, preserve_added: bool, source: &str, empty_sides: EmptySides) -> Parsed {
+    let mut parsed = Parsed::default();
+
+    for (block, card) in cards {
+        let card = match card {
+            Ok(card) => card,
+            Err(message) => {
+                parsed.errors.push(ParseError { block, message });
+                continue;
+            }
+        };
+
+        if let Some(message) = check_sides(&card.front, &card.back) {
+            let problem = ParseError { block, message };
+            match empty_sides {
+                EmptySides::Reject => {
+                    parsed.errors.push(problem);
+                    continue;
+                },
+                EmptySides::Warn => parsed.warnings.push(problem),
+            }
+        }
+
+        let added = match card.added {
+            Some(added) if preserve_added => added,
+            _ => *today,
+        };
+
+        let schedule = card.schedule.unwrap_or(Review { level: 1, next: *today, ease_factor: INITIAL_EASE, repetitions: 0, interval_days: 0 });
+        let c = Flashcard {
+            id: -1, // dummy value
+            deck_id,
+            front: card.front,
+            back: card.back,
+            added,
+            next: schedule.next,
+            level: schedule.level,
+            ease_factor: schedule.ease_factor,
+            repetitions: schedule.repetitions,
+            interval_days: schedule.interval_days,
+            note_type_id: None,
+            source: Some(source.to_string()),
+            suspended: false,
+            tags: card.tags,
+        };
+
+        parsed.cards.push(c);
+    }
+
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(cards: &[&str], empty_sides: EmptySides) -> Parsed {
+        let today = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        parse_cards(1, parse_blocks(cards), &today, false, "test", empty_sides)
+    }
+
+    #[test]
+    fn empty_sides_are_rejected_by_default_policy() {
+        let parsed = parse(&["\nQ\n<>\nA\n", "\n  \n<>\nA\n", "\nQ\n<>\n\n"], EmptySides::Reject);
+        assert_eq!(parsed.cards.len(), 1);
+        let errors: Vec<(usize, &str)> = parsed.errors.iter().map(|e| (e.block, e.message.as_str())).collect();
+        assert_eq!(errors, vec![(2, "empty front"), (3, "empty back")]);
+    }
+
+    #[test]
+    fn separators_only_count_on_their_own_line() {
+        let blocks = split_at_lines("Deck Name: Code\n----\nif a <> b\n <> \n\\----\n----\n", CARD_SEPARATOR);
+        assert_eq!(blocks, vec!["Deck Name: Code\n", "if a <> b\n <> \n\\----\n", ""]);
+
+        let cards = parse_blocks(&blocks[1..]);
+        assert_eq!(cards.len(), 1);
+        let card = cards[0].1.as_ref().unwrap();
+        assert_eq!((card.front.as_str(), card.back.as_str()), ("if a <> b", "----"));
+    }
+
+    #[test]
+    fn escape_round_trips() {
+        for side in ["<>", "a\n----\nb", "\\<>", "  \\\\----\n", "x <> y"] {
+            assert_eq!(unescape(&escape(side)), side);
+        }
+        assert_eq!(escape("a\n<>\n"), "a\n\\<>\n");
+    }
+
+    // Cards exported in the flash format and read back, as `add cards` would.
+    fn round_trip(front: &str, back: &str, anonymize: bool) -> RawCard {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+        let card = Flashcard {
+            id: 1,
+            deck_id: 1,
+            front: front.to_string(),
+            back: back.to_string(),
+            added: date,
+            next: date,
+            level: 1,
+            ease_factor: INITIAL_EASE,
+            repetitions: 0,
+            interval_days: 0,
+            note_type_id: None,
+            source: None,
+            suspended: false,
+            tags: vec!["animal".to_string()],
+        };
+        let file = crate::export::to_flash("Deck", &[card], anonymize);
+        let blocks = split_at_lines(&file, CARD_SEPARATOR);
+        parse_blocks(&blocks[1..]).remove(0).1.unwrap()
+    }
+
+    #[test]
+    fn metadata_looking_text_round_trips() {
+        for anonymize in [false, true] {
+            let card = round_trip("tags: a list\nof things", "added: never\ntags: animal", anonymize);
+            assert_eq!((card.front.as_str(), card.back.as_str()), ("tags: a list\nof things", "added: never\ntags: animal"));
+        }
+        let card = round_trip("added: 2024-01-01", "cat", true);
+        assert_eq!((card.front.as_str(), card.added), ("added: 2024-01-01", None));
+    }
+
+    #[test]
+    fn tags_after_the_front_are_rejected() {
+        let cards = parse_blocks(&["tags: pet\ncat\n<>\nkatze\ntags: animal\n"]);
+        assert!(cards[0].1.is_err());
+
+        let cards = parse_blocks(&["added: soon\ncat\n<>\nkatze\n"]);
+        let card = cards[0].1.as_ref().unwrap();
+        assert_eq!((card.front.as_str(), card.added), ("added: soon\ncat", None));
+    }
+
+    #[test]
+    fn empty_sides_can_be_kept_with_a_warning() {
+        let parsed = parse(&["\n<>\nA\n"], EmptySides::Warn);
+        assert_eq!(parsed.cards.len(), 1);
+        assert!(parsed.errors.is_empty());
+        assert_eq!(parsed.warnings[0].message, "empty front");
+    }
+}
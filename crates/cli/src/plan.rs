@@ -0,0 +1,176 @@
+// `study-plan`: which decks to study, and how many cards from each, to fit
+// a time budget.
+//
+// Decks with overdue cards come first, then the ones with the most due cards.
+// Time per card is estimated from recorded sessions.
+
+use rusqlite::{Connection, Result};
+use log::debug;
+
+use crate::args::take_parsed;
+use crate::deck::{deck_label, get_active_deck_ids};
+use crate::{count_due, today, DueFilter};
+
+// Used until enough sessions have been recorded to estimate from.
+const DEFAULT_SECONDS_PER_CARD: f64 = 10.0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeckDue {
+    pub id: i32,
+    pub due: u32,
+    pub overdue: u32,
+}
+
+/// Orders decks by urgency and gives each as many of its due cards as still
+/// fit in `budget` cards; decks left with no cards are dropped.
+pub fn plan(mut decks: Vec<DeckDue>, budget: Option<u32>) -> Vec<(DeckDue, u32)> {
+    decks.retain(|deck| deck.due > 0);
+    decks.sort_by(|a, b| b.overdue.cmp(&a.overdue).then(b.due.cmp(&a.due)).then(a.id.cmp(&b.id)));
+
+    let mut left = budget.unwrap_or(u32::MAX);
+    let mut planned = Vec::new();
+    for deck in decks {
+        if left == 0 {
+            break;
+        }
+        let cards = deck.due.min(left);
+        left -= cards;
+        planned.push((deck, cards));
+    }
+    planned
+}
+
+/// Average seconds spent per reviewed card over all recorded sessions, and
+/// the number of sessions it's based on.
+fn seconds_per_card(conn: &Connection) -> Result<(Option<f64>, u32)> {
+    debug!("seconds_per_card");
+    conn.query_row(
+        "SELECT SUM((julianday(ended_at) - julianday(started_at)) * 86400), SUM(cards_reviewed), COUNT(*)
+        FROM sessions WHERE cards_reviewed > 0",
+        [],
+        |row| {
+            let seconds: Option<f64> = row.get(0)?;
+            let cards: Option<u32> = row.get(1)?;
+            let per_card = match (seconds, cards) {
+                (Some(seconds), Some(cards)) if seconds > 0.0 && cards > 0 => Some(seconds / cards as f64),
+                _ => None,
+            };
+            Ok((per_card, row.get(2)?))
+        },
+    )
+}
+
+fn get_deck_dues(conn: &Connection) -> Result<Vec<DeckDue>> {
+    let today = today();
+    let mut decks = Vec::new();
+    for id in get_active_deck_ids(conn)? {
+        decks.push(DeckDue {
+            id,
+            due: count_due(conn, id, &today, DueFilter::Due, false)? as u32,
+            overdue: count_due(conn, id, &today, DueFilter::Overdue, false)? as u32,
+        });
+    }
+    Ok(decks)
+}
+
+fn minutes(cards: u32, per_card: f64) -> f64 {
+    cards as f64 * per_card / 60.0
+}
+
+/// `study-plan [--minutes M]`: a recommended order of active decks with
+/// per-deck card counts; with `--minutes`, only as many cards as fit.
+pub fn study_plan(conn: &Connection, args: &[String]) {
+    let mut args = args.to_vec();
+
+    let budget_minutes: Option<f64> = match take_parsed(&mut args, "--minutes") {
+        Ok(Some(minutes)) if minutes > 0.0 => Some(minutes),
+        Ok(Some(minutes)) => {
+            fail!("--minutes must be positive, got {}", minutes);
+            return;
+        },
+        Ok(None) => None,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    let (per_card, sessions) = match seconds_per_card(conn) {
+        Ok(estimate) => estimate,
+        Err(e) => {
+            fail!("Error reading sessions: {}", e);
+            return;
+        }
+    };
+    let per_card = match per_card {
+        Some(per_card) => {
+            println!("Estimated {:.1}s per card from {} sessions", per_card, sessions);
+            per_card
+        },
+        None => {
+            println!("No sessions recorded yet; assuming {:.0}s per card", DEFAULT_SECONDS_PER_CARD);
+            DEFAULT_SECONDS_PER_CARD
+        }
+    };
+
+    let decks = match get_deck_dues(conn) {
+        Ok(decks) => decks,
+        Err(e) => {
+            fail!("Error counting due cards: {}", e);
+            return;
+        }
+    };
+    let total_due: u32 = decks.iter().map(|deck| deck.due).sum();
+
+    // At least one card, so a budget shorter than a card still suggests something.
+    let budget = budget_minutes.map(|minutes| ((minutes * 60.0 / per_card).floor() as u32).max(1));
+    let planned = plan(decks, budget);
+    if planned.is_empty() {
+        println!("Nothing is due");
+        return;
+    }
+
+    let mut total = 0;
+    for (i, (deck, cards)) in planned.iter().enumerate() {
+        let label = match deck_label(conn, deck.id) {
+            Ok(label) => label,
+            Err(e) => {
+                fail!("Error reading deck: {}", e);
+                return;
+            }
+        };
+        println!(
+            "{:>3}. {:>5} cards ({} overdue)  ~{:.0} min  {}",
+            i + 1,
+            cards,
+            deck.overdue.min(*cards),
+            minutes(*cards, per_card).ceil(),
+            label,
+        );
+        total += cards;
+    }
+    println!("Total: {} of {} due cards, ~{:.0} min", total, total_due, minutes(total, per_card).ceil());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deck(id: i32, due: u32, overdue: u32) -> DeckDue {
+        DeckDue { id, due, overdue }
+    }
+
+    #[test]
+    fn overdue_decks_come_first_then_by_due_count() {
+        let planned = plan(vec![deck(1, 5, 0), deck(2, 3, 2), deck(3, 9, 0), deck(4, 0, 0)], None);
+        let ids: Vec<i32> = planned.iter().map(|(deck, _)| deck.id).collect();
+        assert_eq!(ids, vec![2, 3, 1]);
+    }
+
+    #[test]
+    fn budget_is_filled_in_order() {
+        let planned = plan(vec![deck(1, 5, 1), deck(2, 8, 0), deck(3, 4, 0)], Some(10));
+        let counts: Vec<(i32, u32)> = planned.iter().map(|(deck, cards)| (deck.id, *cards)).collect();
+        assert_eq!(counts, vec![(1, 5), (2, 5)]);
+    }
+}
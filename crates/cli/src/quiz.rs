@@ -0,0 +1,1432 @@
+use std::collections::VecDeque;
+use std::fs::read_to_string;
+use std::time::{Duration, Instant};
+
+use rusqlite::{params, Connection, Result, ToSql};
+use chrono::{NaiveDate, Local};
+use crossterm::cursor::MoveTo;
+use crossterm::event::{read, poll, Event, KeyCode};
+use crossterm::execute;
+use crossterm::terminal::{Clear, ClearType};
+use rand::seq::SliceRandom;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use log::debug;
+
+use crate::args::{take_flag, take_parsed, take_value};
+use crate::config::Config;
+use crate::deck::{deck_label, get_active_deck_ids, get_deck_direction, get_deck_weight, get_pinned_deck_names, Direction};
+use crate::order::{interleave, warm_up, weighted_shuffle, QuizOrder};
+use crate::scheduler::{schedule, CardState, Grade, Review};
+use crate::session_report::{self, SessionReport};
+use crate::template::render_card;
+use crate::{answer, journal, json, tags, today, typos};
+use crate::{count_due, count_sessions_on, dry_run, first_line, get_deck_id, get_deck_name, get_meta, insert_session, set_meta, Session, increment_review_counts, insert_flashcard_log, suspension_sql, update_flashcard_schedule, DueFilter, FlashcardLog, Savepoint, DIFFICULTY_SQL};
+
+// A card as selected for a quiz session.
+struct QuizCard {
+    id: i32,
+    deck_id: i32,
+    level: i32,
+    front: String,
+    back: String,
+    next: NaiveDate,
+    note_type_id: Option<i32>,
+    difficulty: f64,
+    order_index: i32,
+    review_count: i32,
+    // Sorted; see `tags`.
+    tags: Vec<String>,
+    ease_factor: f64,
+    repetitions: i32,
+    interval_days: i32,
+}
+
+impl QuizCard {
+    fn state(&self) -> CardState {
+        CardState { level: self.level, ease_factor: self.ease_factor, repetitions: self.repetitions, interval_days: self.interval_days }
+    }
+}
+
+// The columns `quiz_card_from_row` reads. None are shared with
+// `flashcard_log`, so they can be selected unqualified from a join with it.
+fn quiz_card_columns() -> String {
+    format!("id, level, front, back, next, note_type_id, deck_id, {}, order_index, review_count, ease_factor, repetitions, interval_days, {}", DIFFICULTY_SQL, tags::tags_sql("id"))
+}
+
+fn quiz_card_from_row(row: &rusqlite::Row) -> Result<QuizCard> {
+    Ok(QuizCard {
+        id: row.get(0)?,
+        level: row.get(1)?,
+        front: row.get(2)?,
+        back: row.get(3)?,
+        next: row.get(4)?,
+        note_type_id: row.get(5)?,
+        deck_id: row.get(6)?,
+        difficulty: row.get(7)?,
+        order_index: row.get(8)?,
+        review_count: row.get(9)?,
+        ease_factor: row.get(10)?,
+        repetitions: row.get(11)?,
+        interval_days: row.get(12)?,
+        tags: tags::from_sql(row.get(13)?),
+    })
+}
+
+// Which cards a session quizzes.
+enum Selection {
+    Due(DueFilter),
+    // Cards answered wrong on or after the given date, due or not.
+    WrongSince(NaiveDate),
+    // Every card, due or not; only for `--practice`, which doesn't reschedule.
+    All,
+}
+
+// With `tag`, only cards carrying it; see `tags`.
+fn select_cards(conn: &Connection, deck_id: i32, selection: &Selection, today: &NaiveDate, include_suspended: bool, tag: Option<&str>) -> Result<Vec<QuizCard>> {
+    let suspension = suspension_sql(include_suspended);
+    let (sql, date, id_column) = match selection {
+        Selection::Due(filter) => {
+            debug!("select_cards: deck_id={} due on or before {}", deck_id, today);
+            (format!("SELECT {} FROM flashcards WHERE deck_id = ?1 and {} and {}", quiz_card_columns(), filter.sql("?2"), suspension), Some(today), "id")
+        },
+        Selection::WrongSince(since) => {
+            debug!("select_cards: deck_id={} answered wrong since {}", deck_id, since);
+            // Log rows from before reviewed_at was recorded never match a date.
+            (format!("SELECT DISTINCT {}
+            FROM flashcards f JOIN flashcard_log l ON l.question_id = f.id
+            WHERE f.deck_id = ?1 AND NOT l.answer AND l.reviewed_at >= ?2 AND {}", quiz_card_columns(), suspension), Some(since), "f.id")
+        },
+        Selection::All => {
+            debug!("select_cards: deck_id={} all cards", deck_id);
+            (format!("SELECT {} FROM flashcards WHERE deck_id = ?1 and {}", quiz_card_columns(), suspension), None, "id")
+        },
+    };
+
+    // Only the parameters the SQL refers to can be bound.
+    let mut values: Vec<&dyn ToSql> = vec![&deck_id];
+    values.extend(date.map(|date| date as &dyn ToSql));
+    let tag_filter = match &tag {
+        Some(tag) => {
+            values.push(tag);
+            format!(" AND {}", tags::tagged_sql(id_column, &format!("?{}", values.len())))
+        },
+        None => String::new(),
+    };
+
+    let mut stmt = conn.prepare(&format!("{}{} ORDER BY {}", sql, tag_filter, id_column))?;
+    let cards = stmt.query_map(&values[..], quiz_card_from_row)?.collect();
+    cards
+}
+
+// Front and back as shown to the user; note-type cards are rendered from their templates.
+fn card_sides(conn: &Connection, card: &QuizCard) -> Result<(String, String)> {
+    match card.note_type_id {
+        Some(note_type_id) => render_card(conn, card.id, note_type_id),
+        None => Ok((card.front.clone(), card.back.clone())),
+    }
+}
+
+// Lists a session's cards in order instead of quizzing them. Every card is
+// rendered before anything is printed, so an error doesn't leave half a JSON
+// array behind.
+fn preview(conn: &Connection, cards: &[QuizCard], as_json: bool) -> Result<()> {
+    let mut lines = Vec::with_capacity(cards.len());
+    for (i, card) in cards.iter().enumerate() {
+        let (front, back) = card_sides(conn, card)?;
+        lines.push(if as_json {
+            format!(
+                "  {{\"id\": {}, \"front\": {}, \"back\": {}, \"level\": {}, \"next\": \"{}\"}}{}",
+                card.id,
+                json::string(front.trim()),
+                json::string(back.trim()),
+                card.level,
+                card.next,
+                if i + 1 < cards.len() { "," } else { "" },
+            )
+        } else {
+            format!("{:>5}  level {:<2}  next {}  {}", card.id, card.level, card.next, first_line(&front))
+        });
+    }
+
+    if as_json {
+        println!("[");
+    }
+    for line in &lines {
+        println!("{}", line);
+    }
+    if as_json {
+        println!("]");
+    }
+    Ok(())
+}
+
+// Where grades come from: the keyboard, an answers file for headless runs, or
+// answers typed on stdin and checked against the card's back.
+enum Answers {
+    Keyboard,
+    File(std::vec::IntoIter<Grade>),
+    Typed,
+}
+
+impl Answers {
+    // With `quality`, digits are SM-2 qualities 0-5; see `Grade::parse_quality`.
+    fn from_file(path: &str, quality: bool) -> std::result::Result<Answers, String> {
+        let file = read_to_string(path).map_err(|e| format!("Error reading answers file: {}", e))?;
+
+        let mut grades = Vec::new();
+        for (i, line) in file.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let grade = if quality { Grade::parse_quality(line) } else { Grade::parse(line) };
+            match grade {
+                Some(grade) => grades.push(grade),
+                None => return Err(format!("Invalid grade on line {} of {}: {}", i + 1, path, line.trim())),
+            }
+        }
+
+        Ok(Answers::File(grades.into_iter()))
+    }
+
+    fn is_interactive(&self) -> bool {
+        matches!(self, Answers::Keyboard)
+    }
+}
+
+// Reads one typed answer from stdin; None once input is exhausted.
+fn read_typed_answer() -> Option<String> {
+    let mut line = String::new();
+    match std::io::stdin().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line),
+    }
+}
+
+// How long the input has to stay quiet before the buffer counts as drained.
+// A zero timeout spun the CPU and missed key-repeat events still in flight.
+const DRAIN_QUIET: Duration = Duration::from_millis(5);
+
+// Terminal events, behind a trait so draining can be tested without a terminal.
+trait EventSource {
+    fn poll(&mut self, timeout: Duration) -> bool;
+    fn read(&mut self) -> Event;
+}
+
+struct Terminal;
+
+impl EventSource for Terminal {
+    fn poll(&mut self, timeout: Duration) -> bool {
+        poll(timeout).unwrap()
+    }
+
+    fn read(&mut self) -> Event {
+        read().unwrap()
+    }
+}
+
+// Discards every event that is pending or arrives within DRAIN_QUIET of the
+// last one: keys typed ahead or auto-repeated while the previous card was
+// being graded, and non-key events (resize, focus, ...). Without this a held
+// `1` grades the following cards too. Returns how many events were dropped.
+fn drain_pending(events: &mut impl EventSource) -> usize {
+    let mut drained = 0;
+    while events.poll(DRAIN_QUIET) {
+        events.read();
+        drained += 1;
+    }
+    drained
+}
+
+fn clear_key_buffer() {
+    let drained = drain_pending(&mut Terminal);
+    if drained > 0 {
+        debug!("quiz: discarded {} pending events", drained);
+    }
+}
+
+/// What happens when a card's grade timeout runs out; see `grade_timeout_action`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum TimeoutAction {
+    Fail,
+    Skip,
+    Reveal,
+}
+
+impl TimeoutAction {
+    pub fn parse(name: &str) -> Option<TimeoutAction> {
+        match name {
+            "fail" => Some(TimeoutAction::Fail),
+            "skip" => Some(TimeoutAction::Skip),
+            "reveal" => Some(TimeoutAction::Reveal),
+            _ => None,
+        }
+    }
+}
+
+/// How often a quiz commits its grades; see `autosave_interval`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AutosaveInterval {
+    Cards(u32),
+    Seconds(u64),
+}
+
+impl AutosaveInterval {
+    /// `N` for every N cards, `Ns` for every N seconds.
+    pub fn parse(value: &str) -> Option<AutosaveInterval> {
+        match value.strip_suffix('s') {
+            Some(secs) => secs.parse().ok().filter(|&secs| secs >= 1).map(AutosaveInterval::Seconds),
+            None => value.parse().ok().filter(|&cards| cards >= 1).map(AutosaveInterval::Cards),
+        }
+    }
+}
+
+// Batches a session's writes into one transaction per autosave interval.
+// With the default of one card every grade is committed on its own and no
+// transaction is opened. A crash loses at most the uncommitted batch; grades
+// still to be committed when the session ends are committed then.
+struct Autosave<'a> {
+    conn: &'a Connection,
+    interval: AutosaveInterval,
+    batch: Option<Savepoint<'a>>,
+    cards: u32,
+    since: Instant,
+}
+
+impl<'a> Autosave<'a> {
+    fn new(conn: &'a Connection, interval: AutosaveInterval) -> Autosave<'a> {
+        Autosave { conn, interval, batch: None, cards: 0, since: Instant::now() }
+    }
+
+    // Called before a card's writes.
+    fn begin(&mut self) -> Result<()> {
+        if self.batch.is_none() && self.interval != AutosaveInterval::Cards(1) {
+            self.batch = Some(Savepoint::new(self.conn, "autosave")?);
+            self.cards = 0;
+            self.since = Instant::now();
+        }
+        Ok(())
+    }
+
+    // Called after a card's writes; commits once the interval is reached.
+    fn card_done(&mut self) -> Result<()> {
+        self.cards += 1;
+        let due = match self.interval {
+            AutosaveInterval::Cards(cards) => self.cards >= cards,
+            AutosaveInterval::Seconds(secs) => self.since.elapsed() >= Duration::from_secs(secs),
+        };
+        if due {
+            self.finish()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        match self.batch.take() {
+            Some(batch) => {
+                debug!("quiz: autosave after {} cards", self.cards);
+                batch.commit()
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+// Accuracy over the last `size` grades of a session.
+struct AccuracyWindow {
+    size: usize,
+    recent: VecDeque<bool>,
+}
+
+impl AccuracyWindow {
+    fn new(size: usize) -> AccuracyWindow {
+        AccuracyWindow { size, recent: VecDeque::with_capacity(size) }
+    }
+
+    fn push(&mut self, correct: bool) {
+        if self.recent.len() == self.size {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(correct);
+    }
+
+    // Only a full window counts, so a bad first card doesn't end the session.
+    fn is_below(&self, threshold: f64) -> bool {
+        let correct = self.recent.iter().filter(|&&c| c).count();
+        self.recent.len() == self.size && (correct as f64) < threshold * self.size as f64
+    }
+}
+
+// How long a revealed card's back stays up before a grade is accepted, so a
+// key pressed just as time ran out doesn't grade it unseen.
+const REVEAL_PAUSE: Duration = Duration::from_secs(2);
+
+// What's on screen for the current card, kept so it can be drawn again
+// when the terminal is resized.
+#[derive(Default)]
+struct CardView {
+    lines: Vec<String>,
+}
+
+impl CardView {
+    fn show(&mut self, line: String) {
+        println!("{}", line);
+        self.lines.push(line);
+    }
+
+    // Clears the screen and prints the card again, so the terminal lays it
+    // out for its new width instead of leaving the old wrapping behind.
+    fn redraw(&self) {
+        if self.lines.is_empty() {
+            return;
+        }
+        if let Err(e) = execute!(std::io::stdout(), Clear(ClearType::All), MoveTo(0, 0)) {
+            debug!("quiz: can't clear the screen: {}", e);
+            return;
+        }
+        for line in &self.lines {
+            println!("{}", line);
+        }
+    }
+}
+
+// The next key event, or None once `deadline` has passed. A resize calls
+// `on_resize` and keeps waiting.
+fn next_key(events: &mut impl EventSource, deadline: Option<Instant>, on_resize: &mut dyn FnMut()) -> Option<KeyCode> {
+    loop {
+        if let Some(deadline) = deadline {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() || !events.poll(left) {
+                return None;
+            }
+        }
+        // Wait for an event
+        match events.read() {
+            Event::Key(key_event) => return Some(key_event.code),
+            Event::Resize(columns, rows) => {
+                debug!("quiz: terminal resized to {}x{}", columns, rows);
+                on_resize();
+            },
+            _ => (),
+        }
+    }
+}
+
+// What waiting for a key ended with.
+enum Wait<T> {
+    Key(T),
+    TimedOut,
+    // `q`: stop the session, leaving the current card as it was.
+    Quit,
+}
+
+// Cards flagged with `f` to come back to in the wrap-up pass at the end of
+// the session, with their sides as shown. Flags only last for the session.
+#[derive(Default)]
+struct Flags {
+    cards: Vec<(i32, String, String)>,
+}
+
+impl Flags {
+    // Flags the card, or unflags it if it's flagged; returns whether it's flagged now.
+    fn toggle(&mut self, id: i32, front: &str, back: &str) -> bool {
+        match self.cards.iter().position(|(flagged, _, _)| *flagged == id) {
+            Some(i) => {
+                self.cards.remove(i);
+                false
+            },
+            None => {
+                self.cards.push((id, front.to_string(), back.to_string()));
+                true
+            }
+        }
+    }
+}
+
+// `f` while waiting for a key: `on_flag` toggles the current card's flag.
+fn show_flag(flagged: bool, view: &mut CardView) {
+    view.show(if flagged { "Flagged for the wrap-up" } else { "Unflagged" }.to_string());
+}
+
+fn wait_for_flip(deadline: Option<Instant>, view: &mut CardView, on_flag: &mut dyn FnMut() -> bool) -> Wait<()> {
+    loop {
+        match next_key(&mut Terminal, deadline, &mut || view.redraw()) {
+            Some(KeyCode::Enter) => return Wait::Key(()),
+            Some(KeyCode::Char('f')) => show_flag(on_flag(), view),
+            Some(KeyCode::Char('q')) => return Wait::Quit,
+            Some(_) => (),
+            None => return Wait::TimedOut,
+        }
+    }
+}
+
+// Waits for enter; false if `q` was pressed instead.
+fn wait_for_enter(view: &CardView) -> bool {
+    loop {
+        match next_key(&mut Terminal, None, &mut || view.redraw()) {
+            Some(KeyCode::Enter) => return true,
+            Some(KeyCode::Char('q')) => return false,
+            _ => (),
+        }
+    }
+}
+
+// Shows the flagged cards again, front then back, ungraded; `q` ends it early.
+fn wrap_up(flags: &Flags) {
+    let total = flags.cards.len();
+    println!("== Wrap-up: {} flagged cards ==", total);
+    clear_key_buffer();
+    for (i, (id, front, back)) in flags.cards.iter().enumerate() {
+        let mut view = CardView::default();
+        view.show(format!("Flagged {}/{} (card {})", i + 1, total, id));
+        view.show(front.clone());
+        view.show("press enter to flip".to_string());
+        if !wait_for_enter(&view) {
+            return;
+        }
+        view.show(answer::display(back));
+        view.show(if i + 1 < total { "press enter for the next card" } else { "press enter to finish" }.to_string());
+        if !wait_for_enter(&view) {
+            return;
+        }
+    }
+}
+
+fn grade_prompt(quality: bool) -> &'static str {
+    if quality {
+        "Press - quality 0-5 (0: blackout, 2: wrong, 3: hard, 5: perfect), Flag: f, Quit: q"
+    } else {
+        "Press - O: 1, X: 2, Hard: 3, Easy: 4, Flag: f, Quit: q"
+    }
+}
+
+fn read_grade(deadline: Option<Instant>, quality: bool, view: &mut CardView, on_flag: &mut dyn FnMut() -> bool) -> Wait<Grade> {
+    loop {
+        let key = match next_key(&mut Terminal, deadline, &mut || view.redraw()) {
+            Some(key) => key,
+            None => return Wait::TimedOut,
+        };
+        match key {
+            KeyCode::Char(c @ '0'..='5') if quality => return Wait::Key(Grade::Quality(c as u8 - b'0')),
+            KeyCode::Char('1') => return Wait::Key(Grade::Good),
+            KeyCode::Char('2') => return Wait::Key(Grade::Again),
+            KeyCode::Char('3') => return Wait::Key(Grade::Hard),
+            KeyCode::Char('4') => return Wait::Key(Grade::Easy),
+            KeyCode::Char('f') => show_flag(on_flag(), view),
+            KeyCode::Char('q') => return Wait::Quit,
+            _ => (),
+        }
+    }
+}
+
+const ONBOARDING: &str = "\
+Welcome to flash! Each card shows its front first; try to recall the back.
+  - Press enter to flip the card and see the answer.
+  - Then grade yourself: 1 if you knew it, 2 if you didn't,
+    3 if it was hard, 4 if it was easy.
+  - Press f to flag a card; flagged cards are shown again at the end.
+  - Press q to stop early; the cards you haven't graded stay due.
+Cards you know come back after longer and longer intervals, growing faster
+for cards you find easy. A card you miss starts over and comes back tomorrow.
+";
+
+// Shows a short orientation the first time someone studies with a database
+// that has never logged a review. Once shown, it's recorded in `meta`.
+fn show_onboarding(conn: &Connection) -> Result<()> {
+    if get_meta(conn, "onboarding_shown")?.is_some() {
+        return Ok(());
+    }
+    let reviews: i32 = conn.query_row("SELECT COUNT(*) FROM flashcard_log", [], |row| row.get(0))?;
+    if reviews == 0 {
+        println!("{}", ONBOARDING);
+    }
+    set_meta(conn, "onboarding_shown", "1")
+}
+
+/// Reschedules a card according to `grade` and records the answer in the log,
+/// all or nothing.
+pub fn apply_grade(conn: &Connection, config: &Config, id: i32, card: &CardState, grade: Grade, today: NaiveDate) -> Result<Review> {
+    let review = schedule(config.scheduler(), today, card, grade, config.min_interval_days());
+    debug!("quiz: card {} graded {}, level {} -> {}, next {}", id, grade, card.level, review.level, review.next);
+
+    let tx = Savepoint::new(conn, "grade")?;
+    update_flashcard_schedule(&tx, id, &review)?;
+    increment_review_counts(&tx, id, !grade.is_correct())?;
+    insert_flashcard_log(&tx, &FlashcardLog {
+        question_id: id,
+        grade,
+        reviewed_at: Local::now().naive_local(),
+    })?;
+    tx.commit()?;
+    Ok(review)
+}
+
+fn get_quiz_cards(conn: &Connection, ids: &[i32]) -> Result<Vec<QuizCard>> {
+    debug!("get_quiz_cards: {} ids", ids.len());
+    let sql = format!("SELECT {} FROM flashcards WHERE id = ?1", quiz_card_columns());
+    let mut stmt = conn.prepare(&sql)?;
+    let mut cards = Vec::new();
+    for id in ids {
+        let card = stmt.query_row(params![id], quiz_card_from_row);
+        match card {
+            Ok(card) => cards.push(card),
+            // Deleted since the session was planned.
+            Err(rusqlite::Error::QueryReturnedNoRows) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(cards)
+}
+
+pub fn quiz(conn: &Connection, config: &Config, args: &[String]) {
+    let mut args = args.to_vec();
+
+    // Opt-in journaling, so a crashed session can be resumed; see `journal`.
+    let resume = take_flag(&mut args, "--resume-from-crash");
+    let mut journaling = take_flag(&mut args, "--journal");
+    let mut queue: Option<Vec<i32>> = None;
+    if resume {
+        match journal::pending(conn) {
+            Ok(Some(pending)) => {
+                println!("Resuming interrupted session: {} cards left", pending.card_ids.len());
+                // Flags given now (e.g. a new `--answers` file) add to the saved ones.
+                let extra = args.split_off(2.min(args.len()));
+                args = pending.args;
+                args.extend(extra);
+                queue = Some(pending.card_ids);
+                journaling = true;
+            },
+            Ok(None) => {
+                fail!("No interrupted session to resume");
+                return;
+            },
+            Err(e) => {
+                fail!("Error reading session journal: {}", e);
+                return;
+            }
+        }
+    } else if let Ok(Some(pending)) = journal::pending(conn) {
+        println!("An interrupted session has {} cards left; run `quiz --resume-from-crash` to continue it", pending.card_ids.len());
+    }
+    // Saved with the journal so a resumed session runs with the same options.
+    let session_args = args.clone();
+
+    let seed: Option<u64> = match take_parsed(&mut args, "--seed") {
+        Ok(seed) => seed,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    // One terse line per card instead of the interactive prompts.
+    let compact = take_flag(&mut args, "--compact-output");
+    // Print how many cards are due and stop before the session starts.
+    let due_only_count = take_flag(&mut args, "--due-only-count");
+    let filter = if take_flag(&mut args, "--only-overdue") { DueFilter::Overdue } else { DueFilter::Due };
+    let include_suspended = take_flag(&mut args, "--include-suspended");
+
+    // Remediation: redo the cards missed since a date. These cards were
+    // already rescheduled when they were missed, so a remediation session
+    // is practice only and writes neither schedule updates nor log entries.
+    let selection = match take_value(&mut args, "--practice-wrong-from") {
+        Ok(Some(date)) => match NaiveDate::parse_from_str(&date, "%Y-%m-%d") {
+            Ok(since) => Selection::WrongSince(since),
+            Err(_) => {
+                fail!("Invalid date for --practice-wrong-from: {} (expected YYYY-MM-DD)", date);
+                return;
+            }
+        },
+        Ok(None) => Selection::Due(filter),
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    // Flip through cards without writing anything: the database is opened
+    // read-only (see `main`), and nothing is scheduled, logged or recorded.
+    let read_only = take_flag(&mut args, "--practice");
+    if read_only && journaling {
+        fail!("--practice can't be combined with --journal or --resume-from-crash");
+        return;
+    }
+    let selection = match (take_flag(&mut args, "--all-cards"), &selection) {
+        (false, _) => selection,
+        (true, Selection::Due(_)) if read_only => Selection::All,
+        (true, Selection::Due(_)) => {
+            fail!("--all-cards needs --practice");
+            return;
+        },
+        (true, _) => {
+            fail!("--all-cards can't be combined with --practice-wrong-from");
+            return;
+        },
+    };
+    let practice = read_only || matches!(selection, Selection::WrongSince(_));
+
+    // Show the session's cards without quizzing; `--json` for other frontends.
+    let preview_only = take_flag(&mut args, "--preview");
+    let as_json = take_flag(&mut args, "--json");
+    if as_json && !preview_only {
+        fail!("--json requires --preview");
+        return;
+    }
+
+    // Pacing for studying in short bursts: each session serves roughly
+    // `due / sessions left today`, out of `sessions_per_day` planned sittings.
+    // Sessions finished today over the same decks count as done. The cap is
+    // taken from the pooled cards after `--per-deck-limit` and ordering.
+    let paced = take_flag(&mut args, "--limit-by-time-of-day");
+
+    // Caps each deck's share of a multi-deck session. Decks are capped before
+    // they're merged, so the shuffle and `--quiz-order` see the capped pool.
+    let per_deck_limit: Option<usize> = match take_parsed(&mut args, "--per-deck-limit") {
+        Ok(limit) => limit,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    // Writes a Markdown summary of the session here once it ends,
+    // replacing the file; see `session_report`.
+    let report_path = match take_value(&mut args, "--report") {
+        Ok(path) => path,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    // Only cards with this tag.
+    let tag = match take_value(&mut args, "--tag") {
+        Ok(tag) => tag,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    // Caps the whole session, after ordering and pacing.
+    let limit: Option<usize> = match take_parsed(&mut args, "--limit") {
+        Ok(limit) => limit,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    let order = match take_value(&mut args, "--quiz-order") {
+        Ok(Some(name)) => match QuizOrder::parse(&name) {
+            Some(order) => order,
+            None => {
+                fail!("Unknown quiz order: {} (supported: shuffle, spaced, difficulty, sequence)", name);
+                return;
+            }
+        },
+        Ok(None) => QuizOrder::Shuffle,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    // Opens the session with this many of its highest-level cards, highest
+    // first. It's applied after `--quiz-order`, which orders the rest, and
+    // before pacing and `--limit`, so the warm-up cards count toward the cap.
+    let warm_up_count: Option<usize> = match take_parsed(&mut args, "--warm-up") {
+        Ok(count) => count,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    // Grade on SM-2's 0-5 quality scale instead of O/X/Hard/Easy.
+    let quality = take_flag(&mut args, "--quality");
+    let mut answers = match take_value(&mut args, "--answers") {
+        Ok(Some(path)) => match Answers::from_file(&path, quality) {
+            Ok(answers) => answers,
+            Err(e) => {
+                fail!("{}", e);
+                return;
+            }
+        },
+        Ok(None) => Answers::Keyboard,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    // Keep keys typed ahead between cards; for debugging input issues.
+    let no_clear = take_flag(&mut args, "--no-clear");
+
+    if take_flag(&mut args, "--type-answer") {
+        if !answers.is_interactive() {
+            fail!("--type-answer can't be combined with --answers");
+            return;
+        }
+        answers = Answers::Typed;
+    }
+
+    // Name each card's deck next to its front; on by default when several
+    // decks are quizzed together.
+    let show_deck = take_flag(&mut args, "--show-deck");
+    let no_show_deck = take_flag(&mut args, "--no-show-deck");
+
+    // Overrides every deck's default direction. `--reverse` and `--both` are
+    // short for `--direction back` and `--direction random`; `--random-side`
+    // is another name for `--both`.
+    let reverse = take_flag(&mut args, "--reverse");
+    let both = take_flag(&mut args, "--both") | take_flag(&mut args, "--random-side");
+    let direction = match take_value(&mut args, "--direction") {
+        Ok(None) if reverse && both => {
+            fail!("--reverse and --both can't be combined");
+            return;
+        },
+        Ok(None) if reverse => Some(Direction::Back),
+        Ok(None) if both => Some(Direction::Random),
+        Ok(None) => None,
+        Ok(Some(_)) if reverse || both => {
+            fail!("--direction can't be combined with --reverse or --both");
+            return;
+        },
+        Ok(Some(name)) => match Direction::parse(&name) {
+            Some(direction) => Some(direction),
+            None => {
+                fail!("Unknown direction: {} (supported: front, back, alternate, random)", name);
+                return;
+            }
+        },
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    // End the session early once recent accuracy drops; see `adaptive_threshold`.
+    let adaptive = take_flag(&mut args, "--difficulty-adaptive-limit");
+
+    // Keep each wrong typed answer for the `typos` report.
+    let capture_typos = take_flag(&mut args, "--capture-typos");
+    if capture_typos && !matches!(answers, Answers::Typed) {
+        fail!("--capture-typos needs --type-answer");
+        return;
+    }
+    if capture_typos && read_only {
+        fail!("--capture-typos can't be combined with --practice");
+        return;
+    }
+
+    if args.len() < 3 {
+        fail!("Missing <deck_name>");
+        // Offer the decks studied daily.
+        if let Ok(pinned) = get_pinned_deck_names(conn) {
+            if !pinned.is_empty() {
+                println!("Pinned decks: {}", pinned.join(", "));
+            }
+        }
+        return;
+    }
+
+    // `quiz all` studies every active deck in one combined session.
+    let deck_ids = if args[2] == "all" {
+        match get_active_deck_ids(conn) {
+            Ok(ids) => ids,
+            Err(e) => {
+                fail!("Error reading decks: {}", e);
+                return;
+            }
+        }
+    } else {
+        match get_deck_id(conn, &args[2]) {
+            Ok(id) => vec![id],
+            Err(e) => {
+                fail!("Error getting deck id: {}", e);
+                return;
+            }
+        }
+    };
+
+    let today = today();
+
+    if due_only_count {
+        let mut total = 0;
+        for &deck_id in &deck_ids {
+            // The count query knows nothing of tags.
+            let count = match &tag {
+                Some(tag) => select_cards(conn, deck_id, &Selection::Due(filter), &today, include_suspended, Some(tag)).map(|cards| cards.len() as i32),
+                None => count_due(conn, deck_id, &today, filter, include_suspended),
+            };
+            match count {
+                Ok(count) => total += count,
+                Err(e) => {
+                    fail!("Error counting due cards: {}", e);
+                    return;
+                }
+            }
+        }
+        println!("{}", total);
+        return;
+    }
+
+    // A fixed seed makes the session order, and random directions, reproducible.
+    let mut rng = match seed {
+        Some(seed) => {
+            debug!("quiz: shuffling with seed {}", seed);
+            StdRng::seed_from_u64(seed)
+        },
+        None => StdRng::from_entropy(),
+    };
+
+    let rows = if let Some(ids) = &queue {
+        match get_quiz_cards(conn, ids) {
+            Ok(rows) => rows,
+            Err(e) => {
+                fail!("Error reading cards: {}", e);
+                return;
+            }
+        }
+    } else {
+        let mut rows = Vec::new();
+        let mut weights: Vec<(i32, u32)> = Vec::new();
+        for &deck_id in &deck_ids {
+            match get_deck_weight(conn, deck_id) {
+                Ok(weight) => weights.push((deck_id, weight)),
+                Err(e) => {
+                    fail!("Error reading deck: {}", e);
+                    return;
+                }
+            }
+            let mut cards = match select_cards(conn, deck_id, &selection, &today, include_suspended, tag.as_deref()) {
+                Ok(cards) => cards,
+                Err(e) => {
+                    fail!("Error selecting cards: {}", e);
+                    return;
+                }
+            };
+            if let Some(limit) = per_deck_limit {
+                // The most overdue cards make the cut.
+                cards.sort_by_key(|card| card.next);
+                cards.truncate(limit);
+            }
+            rows.append(&mut cards);
+        }
+
+        debug!("quiz: {} cards selected", rows.len());
+
+        if deck_ids.len() > 1 {
+            // Across decks, a deck's weight biases how early its cards come up.
+            let weight = |card: &QuizCard| weights.iter().find(|(id, _)| *id == card.deck_id).map_or(1, |(_, w)| *w);
+            rows = weighted_shuffle(rows, weight, &mut rng);
+        } else {
+            rows.shuffle(&mut rng);
+        }
+
+        match order {
+            QuizOrder::Shuffle => {},
+            // A card's topic is its first tag, or else its note type.
+            QuizOrder::Spaced => rows = interleave(rows, |card| {
+                card.tags.first().map(|tag| format!("tag:{}", tag)).or(card.note_type_id.map(|id| format!("note type:{}", id)))
+            }),
+            // Stable, so equally hard cards keep their shuffled order.
+            QuizOrder::Difficulty => rows.sort_by(|a, b| b.difficulty.total_cmp(&a.difficulty)),
+            // The deck's fixed sequence; across decks, cards at the same
+            // position keep their shuffled order.
+            QuizOrder::Sequence => rows.sort_by_key(|card| card.order_index),
+        }
+        if let Some(count) = warm_up_count {
+            rows = warm_up(rows, count, |card| card.level);
+        }
+        rows
+    };
+
+    let session_decks: Vec<String> = deck_ids.iter().filter_map(|&id| get_deck_name(conn, id).ok()).collect();
+    let session_decks = session_decks.join(", ");
+
+    let mut rows = rows;
+    if paced && queue.is_none() {
+        let done = match count_sessions_on(conn, &session_decks, &crate::today()) {
+            Ok(done) => done,
+            Err(e) => {
+                fail!("Error reading sessions: {}", e);
+                return;
+            }
+        };
+        let remaining = config.sessions_per_day().saturating_sub(done).max(1) as usize;
+        let cap = rows.len().div_ceil(remaining);
+        debug!("quiz: pacing {} cards over {} remaining sessions, serving {}", rows.len(), remaining, cap);
+        rows.truncate(cap);
+    }
+    if let Some(limit) = limit {
+        rows.truncate(limit);
+    }
+
+    if preview_only {
+        if let Err(e) = preview(conn, &rows, as_json) {
+            fail!("Error rendering cards: {}", e);
+        }
+        return;
+    }
+
+    if read_only {
+        println!("practice mode: nothing saved");
+    } else if !compact && !matches!(answers, Answers::File(_)) && !rows.is_empty() {
+        if let Err(e) = show_onboarding(conn) {
+            debug!("quiz: onboarding skipped: {}", e);
+        }
+    }
+
+    let labels: Vec<(i32, String)> = deck_ids.iter().filter_map(|&id| Some((id, deck_label(conn, id).ok()?))).collect();
+    if !compact {
+        let names: Vec<&str> = labels.iter().map(|(_, label)| label.as_str()).collect();
+        println!("== {} ==", names.join(", "));
+    }
+    let show_deck = !no_show_deck && (show_deck || deck_ids.len() > 1);
+
+    let mut directions: Vec<(i32, Direction)> = Vec::new();
+    for &deck_id in &deck_ids {
+        match direction.map_or_else(|| get_deck_direction(conn, deck_id), Ok) {
+            Ok(direction) => directions.push((deck_id, direction)),
+            Err(e) => {
+                fail!("Error reading deck: {}", e);
+                return;
+            }
+        }
+    }
+
+    if journaling && queue.is_none() {
+        let ids: Vec<i32> = rows.iter().map(|card| card.id).collect();
+        if let Err(e) = journal::save(conn, &session_args, &ids) {
+            fail!("Error saving session journal: {}", e);
+            return;
+        }
+    }
+
+    let started_at = Local::now().naive_local();
+    let mut window = AccuracyWindow::new(config.adaptive_window());
+    let mut autosave = Autosave::new(conn, config.autosave_interval());
+    let mut reviewed = 0;
+    let mut correct = 0;
+    let mut quit = false;
+    let mut flags = Flags::default();
+    let mut results: Vec<(i32, String, Grade)> = Vec::new();
+
+    let total = rows.len();
+    for (i, card) in rows.into_iter().enumerate() {
+        // Input left over from the previous card must not flip or grade this one.
+        if answers.is_interactive() && !no_clear {
+            clear_key_buffer();
+        }
+
+        let (front, back) = match card_sides(conn, &card) {
+            Ok(sides) => sides,
+            Err(e) => {
+                fail!("Error rendering card {}: {}", card.id, e);
+                quit = true;
+                break;
+            }
+        };
+        let reversed = match directions.iter().find(|(deck_id, _)| *deck_id == card.deck_id).map_or(Direction::Front, |(_, d)| *d) {
+            Direction::Front => false,
+            Direction::Back => true,
+            Direction::Alternate => card.review_count % 2 == 1,
+            Direction::Random => rng.gen_bool(0.5),
+        };
+        // Grading and scheduling don't depend on which side was shown.
+        let (front, back) = if reversed { (back, front) } else { (front, back) };
+        let (id, level, state) = (card.id, card.level, card.state());
+
+        let mut view = CardView::default();
+        if !compact {
+            view.show(format!("Card {}/{}", i + 1, total));
+            match labels.iter().find(|(deck_id, _)| *deck_id == card.deck_id).filter(|_| show_deck) {
+                Some((_, label)) => view.show(format!("[{}] {}", label, front)),
+                None => view.show(front.clone()),
+            }
+        }
+        // Only the keyboard is timed; answers files and typed answers wait.
+        let deadline = config.grade_timeout().filter(|_| answers.is_interactive()).map(|timeout| Instant::now() + timeout);
+        let mut timed_out = false;
+
+        let grade = match &mut answers {
+            Answers::Typed => {
+                if !compact {
+                    println!("{}", if reversed { "type the front" } else { "type your answer" });
+                }
+                let typed = match read_typed_answer() {
+                    Some(typed) => typed,
+                    None => {
+                        debug!("quiz: no more typed answers, stopping");
+                        break;
+                    }
+                };
+                let correct = answer::accepts(&back, &typed);
+                if !correct && capture_typos && !typed.trim().is_empty() {
+                    if let Err(e) = typos::capture(conn, id, &typed) {
+                        fail!("Error saving typo: {}", e);
+                        quit = true;
+                        break;
+                    }
+                }
+                if !compact {
+                    println!("{}", if correct { "Correct" } else { "Incorrect" });
+                    println!("{}", answer::display(&back));
+                }
+                if correct { Grade::Good } else { Grade::Again }
+            },
+            _ => {
+                if !compact {
+                    view.show(if reversed { "press enter to flip to the front" } else { "press enter to flip" }.to_string());
+                }
+
+                if answers.is_interactive() {
+                    match wait_for_flip(deadline, &mut view, &mut || flags.toggle(id, &front, &back)) {
+                        Wait::Key(()) => (),
+                        Wait::TimedOut => timed_out = true,
+                        Wait::Quit => {
+                            quit = true;
+                            break;
+                        }
+                    }
+                }
+
+                if !compact {
+                    view.show(answer::display(&back));
+                }
+                if !timed_out && !compact {
+                    view.show(grade_prompt(quality).to_string());
+                }
+
+                match &mut answers {
+                    Answers::File(grades) => match grades.next() {
+                        Some(grade) => grade,
+                        None => {
+                            debug!("quiz: answers file exhausted, stopping");
+                            break;
+                        }
+                    },
+                    _ if timed_out => Grade::Again,
+                    _ => match read_grade(deadline, quality, &mut view, &mut || flags.toggle(id, &front, &back)) {
+                        Wait::Key(grade) => grade,
+                        Wait::TimedOut => {
+                            timed_out = true;
+                            Grade::Again
+                        },
+                        Wait::Quit => {
+                            quit = true;
+                            break;
+                        }
+                    },
+                }
+            },
+        };
+
+        let grade = if timed_out {
+            let action = config.grade_timeout_action();
+            debug!("quiz: card {} timed out", id);
+            println!("Time's up");
+            match action {
+                TimeoutAction::Fail => grade,
+                TimeoutAction::Skip => {
+                    if compact {
+                        println!("id={} skipped", id);
+                    }
+                    if journaling {
+                        if let Err(e) = journal::mark_done(conn, id) {
+                            fail!("Error updating session journal: {}", e);
+                            quit = true;
+                            break;
+                        }
+                    }
+                    continue;
+                },
+                TimeoutAction::Reveal => {
+                    std::thread::sleep(REVEAL_PAUSE);
+                    clear_key_buffer();
+                    if !compact {
+                        view.show(grade_prompt(quality).to_string());
+                    }
+                    match read_grade(None, quality, &mut view, &mut || flags.toggle(id, &front, &back)) {
+                        Wait::Key(grade) => grade,
+                        // Without a deadline, only quitting ends the wait.
+                        Wait::TimedOut | Wait::Quit => {
+                            quit = true;
+                            break;
+                        }
+                    }
+                },
+            }
+        } else {
+            grade
+        };
+        results.push((id, front.clone(), grade));
+
+        if let Err(e) = autosave.begin() {
+            fail!("Error saving progress: {}", e);
+            quit = true;
+            break;
+        }
+        if practice {
+            if compact {
+                println!("id={} grade={} practice", id, grade);
+            }
+        } else {
+            let review = match apply_grade(conn, config, id, &state, grade, crate::today()) {
+                Ok(review) => review,
+                Err(e) => {
+                    fail!("Error saving review: {}", e);
+                    quit = true;
+                    break;
+                }
+            };
+            reviewed += 1;
+            if grade.is_correct() {
+                correct += 1;
+            }
+
+            if compact {
+                println!("id={} grade={} level={}->{} next={}", id, grade, level, review.level, review.next);
+            }
+        }
+
+        if journaling {
+            if let Err(e) = journal::mark_done(conn, id) {
+                fail!("Error updating session journal: {}", e);
+                quit = true;
+                break;
+            }
+        }
+        if let Err(e) = autosave.card_done() {
+            fail!("Error saving progress: {}", e);
+            quit = true;
+            break;
+        }
+
+        window.push(grade.is_correct());
+        if adaptive && window.is_below(config.adaptive_threshold()) {
+            debug!("quiz: accuracy over the last {} cards is below {}, stopping", config.adaptive_window(), config.adaptive_threshold());
+            println!("That's a tough stretch. Let's pick this up later; the rest of the cards will wait.");
+            break;
+        }
+    }
+
+    if let Err(e) = autosave.finish() {
+        fail!("Error saving progress: {}", e);
+    }
+
+    if quit {
+        // Errors set `quit` as well, also in runs without a terminal.
+        if answers.is_interactive() {
+            clear_key_buffer();
+        }
+        println!("Stopped early: {} cards reviewed", reviewed);
+        // No wrap-up after quitting, but the flagged cards are listed.
+        for (id, front, _) in &flags.cards {
+            println!("Flagged: {}: {}", id, first_line(front));
+        }
+    } else if !flags.cards.is_empty() {
+        wrap_up(&flags);
+    }
+
+    let ended_at = Local::now().naive_local();
+    if let Some(path) = &report_path {
+        let report = SessionReport { started_at, ended_at, decks: session_decks.clone(), results, practice };
+        if dry_run() {
+            println!("Would write the session report to {}", path);
+        } else if let Err(e) = std::fs::write(path, session_report::to_markdown(&report)) {
+            fail!("Error writing {}: {}", path, e);
+        } else {
+            println!("Session report written to {}", path);
+        }
+    }
+
+    // Practice sessions don't touch the review history, so they aren't recorded.
+    if reviewed > 0 {
+        let session = Session {
+            started_at,
+            ended_at,
+            decks: session_decks,
+            cards_reviewed: reviewed,
+            correct,
+        };
+        if let Err(e) = insert_session(conn, &session) {
+            fail!("Error recording session: {}", e);
+        }
+    }
+
+    if journaling {
+        if let Err(e) = journal::clear(conn) {
+            fail!("Error clearing session journal: {}", e);
+        }
+    }
+}
+
+// Reads `card_id,grade` lines; a header line and blank lines are skipped.
+fn parse_grades(text: &str, quality: bool) -> std::result::Result<Vec<(i32, Grade)>, String> {
+    let mut grades = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || (i == 0 && line.starts_with("card_id")) {
+            continue;
+        }
+        let parsed = line.split_once(',').and_then(|(id, grade)| {
+            let grade = if quality { Grade::parse_quality(grade) } else { Grade::parse(grade) };
+            Some((id.trim().parse().ok()?, grade?))
+        });
+        match parsed {
+            Some(card) => grades.push(card),
+            None => return Err(format!("Invalid line {}: {} (expected card_id,grade)", i + 1, line)),
+        }
+    }
+    Ok(grades)
+}
+
+/// `batch-grade <file.csv> [--quality]`: grades cards reviewed away from
+/// flash, such as on paper, from `card_id,grade` lines.
+///
+/// Grades are written as in `--answers` files. Each one is scheduled and
+/// logged as if given in a quiz, all in one transaction.
+pub fn batch_grade(conn: &Connection, config: &Config, args: &[String]) {
+    let mut args = args.to_vec();
+    let quality = take_flag(&mut args, "--quality");
+
+    if args.len() < 3 {
+        fail!("Missing <file>");
+        return;
+    }
+    let grades = match read_to_string(&args[2]).map_err(|e| format!("Error reading file: {}", e)).and_then(|text| parse_grades(&text, quality)) {
+        Ok(grades) => grades,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    let tx = match Savepoint::new(conn, "batch_grade") {
+        Ok(tx) => tx,
+        Err(e) => {
+            fail!("Error starting batch: {}", e);
+            return;
+        }
+    };
+
+    let today = today();
+    let mut missing = Vec::new();
+    let mut applied = 0;
+    for (id, grade) in &grades {
+        // Read inside the batch, so a card graded twice builds on its first grade.
+        let state = match get_quiz_cards(&tx, &[*id]).map(|cards| cards.first().map(QuizCard::state)) {
+            Ok(Some(state)) => state,
+            Ok(None) => {
+                missing.push(id.to_string());
+                continue;
+            },
+            Err(e) => {
+                fail!("Error reading card {}: {}", id, e);
+                return;
+            }
+        };
+        if let Err(e) = apply_grade(&tx, config, *id, &state, *grade, today) {
+            fail!("Error grading card {}: {}", id, e);
+            return;
+        }
+        applied += 1;
+    }
+    debug!("batch_grade: {} of {} grades applied", applied, grades.len());
+
+    if let Err(e) = tx.commit() {
+        fail!("Error saving grades: {}", e);
+        return;
+    }
+    println!("{} grades applied", applied);
+    if !missing.is_empty() {
+        fail!("No cards with ids: {}", missing.join(", "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyEvent, KeyModifiers};
+
+    use super::*;
+
+    #[test]
+    fn batch_grades_are_card_id_and_grade() {
+        let grades = parse_grades("card_id,grade\n3,o\n\n7, x\n", false).unwrap();
+        assert_eq!(grades, vec![(3, Grade::Good), (7, Grade::Again)]);
+        assert_eq!(parse_grades("3,5", true).unwrap(), vec![(3, Grade::Quality(5))]);
+        assert_eq!(parse_grades("3,o\n3", false).unwrap_err(), "Invalid line 2: 3 (expected card_id,grade)");
+    }
+
+    #[test]
+    fn autosave_interval_is_cards_or_seconds() {
+        assert_eq!(AutosaveInterval::parse("1"), Some(AutosaveInterval::Cards(1)));
+        assert_eq!(AutosaveInterval::parse("30s"), Some(AutosaveInterval::Seconds(30)));
+        assert_eq!(AutosaveInterval::parse("0"), None);
+        assert_eq!(AutosaveInterval::parse("s"), None);
+    }
+
+    #[test]
+    fn flags_toggle_and_keep_their_order() {
+        let mut flags = Flags::default();
+        assert!(flags.toggle(3, "Q3", "A3"));
+        assert!(flags.toggle(1, "Q1", "A1"));
+        assert!(!flags.toggle(3, "Q3", "A3"));
+        assert!(flags.toggle(3, "Q3", "A3"));
+        let ids: Vec<i32> = flags.cards.iter().map(|(id, _, _)| *id).collect();
+        assert_eq!(ids, vec![1, 3]);
+    }
+
+    #[test]
+    fn accuracy_window_needs_a_full_window() {
+        let mut window = AccuracyWindow::new(3);
+        window.push(false);
+        window.push(false);
+        assert!(!window.is_below(0.5));
+        window.push(true);
+        assert!(window.is_below(0.5));
+        window.push(true);
+        assert!(!window.is_below(0.5));
+    }
+
+    // Events that are already queued; nothing arrives later.
+    struct Queued(VecDeque<Event>);
+
+    impl EventSource for Queued {
+        fn poll(&mut self, _timeout: Duration) -> bool {
+            !self.0.is_empty()
+        }
+
+        fn read(&mut self) -> Event {
+            self.0.pop_front().unwrap()
+        }
+    }
+
+    fn key(c: char) -> Event {
+        Event::Key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn drain_discards_keys_and_other_events() {
+        let mut events = Queued(VecDeque::from(vec![key('1'), key('1'), Event::Resize(80, 24), Event::Key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE))]));
+        assert_eq!(drain_pending(&mut events), 4);
+        assert!(events.0.is_empty());
+    }
+
+    #[test]
+    fn resizes_redraw_while_waiting_for_a_key() {
+        let mut events = Queued(VecDeque::from(vec![Event::Resize(100, 40), Event::FocusLost, Event::Resize(60, 40), key('1')]));
+        let mut redraws = 0;
+        assert_eq!(next_key(&mut events, None, &mut || redraws += 1), Some(KeyCode::Char('1')));
+        assert_eq!(redraws, 2);
+    }
+
+    #[test]
+    fn drain_with_nothing_pending_reads_nothing() {
+        let mut events = Queued(VecDeque::new());
+        assert_eq!(drain_pending(&mut events), 0);
+    }
+}
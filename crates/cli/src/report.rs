@@ -0,0 +1,923 @@
+// Read-only reports over cards and their review history.
+
+use std::cmp::Reverse;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use rusqlite::{params, Connection, Result, ToSql};
+use chrono::{Days, NaiveDate, NaiveDateTime};
+use log::debug;
+
+use crate::args::{take_flag, take_parsed, take_value};
+use crate::{dry_run, first_line, get_deck_id, get_deck_name, get_flashcard, suspension_sql, today, DueFilter, DIFFICULTY_SQL};
+use crate::scheduler::{add_interval, level_to_date};
+use crate::tags;
+
+/// Estimated probability of recalling a card `elapsed` days after its last
+/// review, given the interval it was scheduled with.
+///
+/// A simple exponential forgetting curve, scaled so that retention is 90% on
+/// the day the card falls due: `R = 0.9 ^ (elapsed / interval)`.
+pub fn estimate_retention(elapsed: i64, interval: i64) -> f64 {
+    0.9f64.powf(elapsed.max(0) as f64 / interval.max(1) as f64)
+}
+
+struct RetentionRow {
+    id: i32,
+    front: String,
+    last_review: NaiveDate,
+    next: NaiveDate,
+    retention: f64,
+}
+
+fn get_retention_rows(conn: &Connection, deck_id: i32, today: &NaiveDate) -> Result<Vec<RetentionRow>> {
+    debug!("get_retention_rows: deck_id={}", deck_id);
+    let mut stmt = conn.prepare(
+        "SELECT f.id, f.front, f.level, f.added, f.next, f.review_count, MAX(l.reviewed_at)
+        FROM flashcards f LEFT JOIN flashcard_log l ON l.question_id = f.id
+        WHERE f.deck_id = ?1
+        GROUP BY f.id"
+    )?;
+
+    let rows = stmt.query_map(params![deck_id], |row| {
+        let level: i32 = row.get(2)?;
+        let added: NaiveDate = row.get(3)?;
+        let next: NaiveDate = row.get(4)?;
+        let review_count: i32 = row.get(5)?;
+        let reviewed_at: Option<NaiveDateTime> = row.get(6)?;
+
+        let last_review = match reviewed_at {
+            Some(reviewed_at) => reviewed_at.date(),
+            // Reviews logged before timestamps were recorded: assume the
+            // card was last scheduled with its current level's interval.
+            None if review_count > 0 => next
+                .checked_sub_days(Days::new(level_to_date(level) as u64))
+                .unwrap_or(added),
+            None => added,
+        };
+
+        let interval = (next - last_review).num_days();
+        let elapsed = (*today - last_review).num_days();
+
+        Ok(RetentionRow {
+            id: row.get(0)?,
+            front: row.get(1)?,
+            last_review,
+            next,
+            retention: estimate_retention(elapsed, interval),
+        })
+    })?.collect();
+    rows
+}
+
+/// The deck's estimated recall averaged over its cards; `None` for an empty deck.
+pub fn mean_retention(conn: &Connection, deck_id: i32, today: &NaiveDate) -> Result<Option<f64>> {
+    let rows = get_retention_rows(conn, deck_id, today)?;
+    if rows.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(rows.iter().map(|row| row.retention).sum::<f64>() / rows.len() as f64))
+}
+
+/// `retention <deck_name> [--threshold 0.9]`: estimated recall per card, lowest first.
+pub fn retention(conn: &Connection, args: &[String]) {
+    let mut args = args.to_vec();
+
+    let threshold: f64 = match take_parsed(&mut args, "--threshold") {
+        Ok(Some(threshold)) if threshold > 0.0 && threshold <= 1.0 => threshold,
+        Ok(Some(threshold)) => {
+            fail!("--threshold must be between 0 and 1, got {}", threshold);
+            return;
+        },
+        Ok(None) => 0.9,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    if args.len() < 3 {
+        fail!("Missing <deck_name>");
+        return;
+    }
+
+    let deck_id = match get_deck_id(conn, &args[2]) {
+        Ok(id) => id,
+        Err(e) => {
+            fail!("Error getting deck id: {}", e);
+            return;
+        }
+    };
+
+    let today = today();
+    let mut rows = match get_retention_rows(conn, deck_id, &today) {
+        Ok(rows) => rows,
+        Err(e) => {
+            fail!("Error reading review history: {}", e);
+            return;
+        }
+    };
+    rows.sort_by(|a, b| a.retention.total_cmp(&b.retention));
+
+    println!("  {:>5}  {:>9}  {:<11}  {:<10}  front", "id", "retention", "last review", "next");
+    let mut below = 0;
+    let mut below_not_due = 0;
+    for row in &rows {
+        let flagged = row.retention < threshold;
+        if flagged {
+            below += 1;
+            if row.next > today {
+                below_not_due += 1;
+            }
+        }
+        println!(
+            "{} {:>5}  {:>8.0}%  {:<11}  {:<10}  {}",
+            if flagged { "*" } else { " " },
+            row.id,
+            row.retention * 100.0,
+            row.last_review,
+            row.next,
+            first_line(&row.front),
+        );
+    }
+
+    println!(
+        "{} of {} cards below {:.0}% ({} not yet due)",
+        below,
+        rows.len(),
+        threshold * 100.0,
+        below_not_due,
+    );
+}
+
+// Cards at this level or above are scheduled 25+ days out.
+pub const MATURE_LEVEL: i32 = 4;
+
+struct Snapshot {
+    total: i32,
+    mature: i32,
+    reviews_today: i32,
+    correct_today: i32,
+}
+
+fn get_snapshot(conn: &Connection, today: &NaiveDate) -> Result<Snapshot> {
+    debug!("get_snapshot: today={}", today);
+    let (total, mature) = conn.query_row(
+        "SELECT COUNT(*), COALESCE(SUM(level >= ?1), 0) FROM flashcards",
+        params![MATURE_LEVEL],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    let (reviews_today, correct_today) = conn.query_row(
+        "SELECT COUNT(*), COALESCE(SUM(answer), 0) FROM flashcard_log WHERE reviewed_at >= ?1",
+        params![today],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    Ok(Snapshot { total, mature, reviews_today, correct_today })
+}
+
+/// `snapshot <path.csv>`: appends today's collection-wide totals to a CSV file.
+///
+/// Meant to be run from cron to build a progress log over time. Columns:
+/// date, total cards, mature cards (level 4+, 25+ day intervals), reviews
+/// today, and today's accuracy (empty when nothing was reviewed).
+pub fn snapshot(conn: &Connection, args: &[String]) {
+    if args.len() < 3 {
+        fail!("Missing <path>");
+        return;
+    }
+    let path = &args[2];
+
+    let today = today();
+    let snapshot = match get_snapshot(conn, &today) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            fail!("Error reading stats: {}", e);
+            return;
+        }
+    };
+
+    let accuracy = if snapshot.reviews_today > 0 {
+        format!("{:.3}", snapshot.correct_today as f64 / snapshot.reviews_today as f64)
+    } else {
+        String::new()
+    };
+    let row = format!("{},{},{},{},{}\n", today, snapshot.total, snapshot.mature, snapshot.reviews_today, accuracy);
+
+    if dry_run() {
+        print!("Would append to {}: {}", path, row);
+        return;
+    }
+
+    let mut file = match OpenOptions::new().create(true).append(true).open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            fail!("Error opening {}: {}", path, e);
+            return;
+        }
+    };
+
+    // A new file starts with the header row.
+    let is_empty = file.metadata().map(|m| m.len() == 0).unwrap_or(false);
+    let contents = if is_empty {
+        format!("date,total_cards,mature_cards,reviews_today,accuracy\n{}", row)
+    } else {
+        row.clone()
+    };
+
+    match file.write_all(contents.as_bytes()) {
+        Ok(_) => {
+            print!("{}", row);
+        },
+        Err(e) => {
+            fail!("Error writing {}: {}", path, e);
+        }
+    }
+}
+
+struct CardRow {
+    id: i32,
+    front: String,
+    back: String,
+    level: i32,
+    next: NaiveDate,
+    review_count: i32,
+    lapse_count: i32,
+    difficulty: f64,
+    suspended: bool,
+    source: Option<String>,
+}
+
+// Which cards `list cards` shows, by when they're next due.
+enum DueWindow {
+    All,
+    // Due on or before the date.
+    Within(NaiveDate),
+    // Due after the date.
+    After(NaiveDate),
+    // Due before the date, as in `quiz --only-overdue`.
+    Overdue(NaiveDate),
+}
+
+impl DueWindow {
+    fn sql(&self) -> String {
+        match self {
+            DueWindow::All => "1".to_string(),
+            DueWindow::Within(_) => "next <= ?2".to_string(),
+            DueWindow::After(_) => "next > ?2".to_string(),
+            DueWindow::Overdue(_) => DueFilter::Overdue.sql("?2"),
+        }
+    }
+
+    fn date(&self) -> Option<NaiveDate> {
+        match self {
+            DueWindow::All => None,
+            DueWindow::Within(date) | DueWindow::After(date) | DueWindow::Overdue(date) => Some(*date),
+        }
+    }
+}
+
+fn get_card_rows(conn: &Connection, deck_id: i32, window: &DueWindow, tag: Option<&str>, order_by: &str) -> Result<Vec<CardRow>> {
+    debug!("get_card_rows: deck_id={} window={:?} tag={:?} order_by={}", deck_id, window.date(), tag, order_by);
+    // A bound parameter the SQL doesn't reference is an error, so ?2 is only
+    // passed when there's a window, and the tag takes the next free number.
+    let date = window.date();
+    let mut values: Vec<&dyn ToSql> = vec![&deck_id];
+    values.extend(date.as_ref().map(|date| date as &dyn ToSql));
+    let tag_filter = match &tag {
+        Some(tag) => {
+            values.push(tag);
+            format!(" AND {}", tags::tagged_sql("id", &format!("?{}", values.len())))
+        },
+        None => String::new(),
+    };
+    let sql = format!(
+        "SELECT id, front, level, next, review_count, lapse_count, {} AS difficulty, suspended, back, source
+        FROM flashcards WHERE deck_id = ?1 AND {}{} ORDER BY {}",
+        DIFFICULTY_SQL,
+        window.sql(),
+        tag_filter,
+        order_by,
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let mapped = |row: &rusqlite::Row| {
+        Ok(CardRow {
+            id: row.get(0)?,
+            front: row.get(1)?,
+            level: row.get(2)?,
+            next: row.get(3)?,
+            review_count: row.get(4)?,
+            lapse_count: row.get(5)?,
+            difficulty: row.get(6)?,
+            suspended: row.get(7)?,
+            back: row.get(8)?,
+            source: row.get(9)?,
+        })
+    };
+    let rows = stmt.query_map(&values[..], mapped)?.collect();
+    rows
+}
+
+/// `list cards <deck_name> [--sort id|difficulty|level|next|sequence] [--due-within N] [--not-due] [--only-overdue] [--tag <tag>] [--show-source]`
+///
+/// Suspended cards are listed too, marked with `S`. `difficulty` puts the
+/// cards with the most lapses per review first.
+///
+/// `--due-within N` lists only cards due within the next N days (0 is today,
+/// overdue cards included); `--not-due` lists the rest, the cards due after
+/// today or, with `--due-within`, after that window. `--only-overdue` lists
+/// only the cards due before today. `--tag` lists only the
+/// cards with that tag. `--show-source` adds a column with the file or
+/// `--source` each card was imported from.
+pub fn list_cards(conn: &Connection, args: &[String]) {
+    let mut args = args.to_vec();
+    let not_due = take_flag(&mut args, "--not-due");
+    let only_overdue = take_flag(&mut args, "--only-overdue");
+    let show_source = take_flag(&mut args, "--show-source");
+    let due_within: Option<u64> = match take_parsed(&mut args, "--due-within") {
+        Ok(days) => days,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    let today = today();
+    let until = add_interval(today, due_within.unwrap_or(0));
+    let window = match (due_within, not_due, only_overdue) {
+        (None, false, true) => DueWindow::Overdue(today),
+        (_, _, true) => {
+            fail!("--only-overdue can't be combined with --due-within or --not-due");
+            return;
+        },
+        (_, true, false) => DueWindow::After(until),
+        (Some(_), false, false) => DueWindow::Within(until),
+        (None, false, false) => DueWindow::All,
+    };
+
+    let tag = match take_value(&mut args, "--tag") {
+        Ok(tag) => tag,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    let order_by = match take_value(&mut args, "--sort") {
+        Ok(sort) => match sort.as_deref() {
+            None | Some("id") => "id",
+            Some("difficulty") => "difficulty DESC, level, id",
+            Some("level") => "level, id",
+            Some("next") => "next, id",
+            Some("sequence") => "order_index, id",
+            Some(sort) => {
+                fail!("Unknown sort: {} (supported: id, difficulty, level, next, sequence)", sort);
+                return;
+            }
+        },
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    if args.len() < 4 {
+        fail!("Missing <deck_name>");
+        return;
+    }
+
+    // Also checks that a deck given by id exists.
+    let deck_id = match get_deck_id(conn, &args[3]).and_then(|id| get_deck_name(conn, id).map(|_| id)) {
+        Ok(id) => id,
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            fail!("No deck with name or id {}", args[3]);
+            return;
+        },
+        Err(e) => {
+            fail!("Error getting deck id: {}", e);
+            return;
+        }
+    };
+
+    let rows = match get_card_rows(conn, deck_id, &window, tag.as_deref(), order_by) {
+        Ok(rows) => rows,
+        Err(e) => {
+            fail!("Error reading flashcards: {}", e);
+            return;
+        }
+    };
+
+    // Sized to the longest source, since they're file paths.
+    let source_width = rows.iter()
+        .filter_map(|row| row.source.as_ref())
+        .map(|source| source.chars().count())
+        .fold("source".len(), usize::max);
+    let source_column = |source: &str| if show_source { format!("{:<1$}  ", source, source_width) } else { String::new() };
+
+    println!(
+        "  {:>5}  {:>5}  {:<10}  {:>7}  {:>6}  {:>10}  {}front <> back",
+        "id", "level", "next", "reviews", "lapses", "difficulty", source_column("source"),
+    );
+    for row in &rows {
+        println!(
+            "{} {:>5}  {:>5}  {:<10}  {:>7}  {:>6}  {:>10.2}  {}{} <> {}",
+            if row.suspended { "S" } else { " " },
+            row.id,
+            row.level,
+            row.next,
+            row.review_count,
+            row.lapse_count,
+            row.difficulty,
+            source_column(row.source.as_deref().unwrap_or("-")),
+            first_line(&row.front),
+            first_line(&row.back),
+        );
+    }
+}
+
+struct HistoryRow {
+    reviewed_at: Option<NaiveDateTime>,
+    deck: String,
+    front: String,
+    correct: bool,
+    // NULL for reviews logged before grades were recorded.
+    grade: Option<String>,
+}
+
+// The latest reviews and manual changes (see `card_events`), newest first, in
+// one deck or all of them. A change reads as its grade, e.g. "Promote (level
+// 2 -> 3)". Reviews logged before timestamps were recorded come last.
+fn get_history(conn: &Connection, deck_id: Option<i32>, limit: u32) -> Result<Vec<HistoryRow>> {
+    debug!("get_history: deck_id={:?} limit={}", deck_id, limit);
+    let mut stmt = conn.prepare(
+        "SELECT at, d.name, f.front, answer, grade
+        FROM (
+            SELECT question_id AS card_id, reviewed_at AS at, answer, grade, rowid AS seq FROM flashcard_log
+            UNION ALL
+            SELECT card_id, at, 0, event || ' (' || detail || ')', rowid FROM card_events
+        ) JOIN flashcards f ON f.id = card_id JOIN decks d ON d.id = f.deck_id
+        WHERE ?1 IS NULL OR f.deck_id = ?1
+        ORDER BY at IS NULL, at DESC, seq DESC LIMIT ?2"
+    )?;
+    let rows = stmt.query_map(params![deck_id, limit], |row| {
+        Ok(HistoryRow {
+            reviewed_at: row.get(0)?,
+            deck: row.get(1)?,
+            front: row.get(2)?,
+            correct: row.get(3)?,
+            grade: row.get(4)?,
+        })
+    })?.collect();
+    rows
+}
+
+/// `history <deck_name> | --all [--limit 20]`: the latest reviews, newest
+/// first, in one deck or across all of them, with the grade each got. Cards
+/// moved with `promote` or `demote` are listed too.
+///
+/// Reviews logged before grades were recorded show Correct or Incorrect.
+pub fn history(conn: &Connection, args: &[String]) {
+    let mut args = args.to_vec();
+    let all = take_flag(&mut args, "--all");
+
+    let limit: u32 = match take_parsed(&mut args, "--limit") {
+        Ok(limit) => limit.unwrap_or(20),
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    let deck_id = match (args.get(2), all) {
+        (Some(_), true) => {
+            fail!("Pass either <deck_name> or --all");
+            return;
+        },
+        (None, false) => {
+            fail!("Missing <deck_name> or --all");
+            return;
+        },
+        (None, true) => None,
+        (Some(deck), false) => match get_deck_id(conn, deck).and_then(|id| get_deck_name(conn, id).map(|_| id)) {
+            Ok(id) => Some(id),
+            Err(rusqlite::Error::QueryReturnedNoRows) => {
+                fail!("No deck with name or id {}", deck);
+                return;
+            },
+            Err(e) => {
+                fail!("Error getting deck id: {}", e);
+                return;
+            }
+        },
+    };
+
+    let rows = match get_history(conn, deck_id, limit) {
+        Ok(rows) => rows,
+        Err(e) => {
+            fail!("Error reading review history: {}", e);
+            return;
+        }
+    };
+
+    if rows.is_empty() {
+        println!("No reviews recorded");
+        return;
+    }
+    for row in &rows {
+        println!(
+            "{:<16}  {}  {} -> {}",
+            row.reviewed_at.map_or_else(|| "-".to_string(), |at| at.format("%Y-%m-%d %H:%M").to_string()),
+            row.deck,
+            first_line(&row.front),
+            row.grade.as_deref().unwrap_or(if row.correct { "Correct" } else { "Incorrect" }),
+        );
+    }
+}
+
+struct SessionRow {
+    id: i32,
+    started_at: NaiveDateTime,
+    ended_at: NaiveDateTime,
+    decks: String,
+    cards_reviewed: i32,
+    correct: i32,
+}
+
+fn get_sessions(conn: &Connection, limit: u32) -> Result<Vec<SessionRow>> {
+    debug!("get_sessions: limit={}", limit);
+    let mut stmt = conn.prepare(
+        "SELECT id, started_at, ended_at, decks, cards_reviewed, correct
+        FROM sessions ORDER BY started_at DESC, id DESC LIMIT ?1"
+    )?;
+    let rows = stmt.query_map(params![limit], |row| {
+        Ok(SessionRow {
+            id: row.get(0)?,
+            started_at: row.get(1)?,
+            ended_at: row.get(2)?,
+            decks: row.get(3)?,
+            cards_reviewed: row.get(4)?,
+            correct: row.get(5)?,
+        })
+    })?.collect();
+    rows
+}
+
+/// `sessions [--limit 10]`: the most recent quiz sessions, newest first.
+pub fn sessions(conn: &Connection, args: &[String]) {
+    let mut args = args.to_vec();
+
+    let limit: u32 = match take_parsed(&mut args, "--limit") {
+        Ok(limit) => limit.unwrap_or(10),
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    let rows = match get_sessions(conn, limit) {
+        Ok(rows) => rows,
+        Err(e) => {
+            fail!("Error reading sessions: {}", e);
+            return;
+        }
+    };
+
+    println!("{:>5}  {:<16}  {:>8}  {:>5}  {:>8}  decks", "id", "started", "duration", "cards", "accuracy");
+    for row in &rows {
+        let duration = (row.ended_at - row.started_at).num_seconds().max(0);
+        println!(
+            "{:>5}  {:<16}  {:>5}:{:02}  {:>5}  {:>7.0}%  {}",
+            row.id,
+            row.started_at.format("%Y-%m-%d %H:%M"),
+            duration / 60,
+            duration % 60,
+            row.cards_reviewed,
+            row.correct as f64 * 100.0 / row.cards_reviewed.max(1) as f64,
+            row.decks,
+        );
+    }
+}
+
+struct CardStats {
+    id: i32,
+    front: String,
+    correct: i32,
+    incorrect: i32,
+}
+
+// Cards with at least one logged review, most failed first. With `since`,
+// only reviews from then on count; older reviews without a timestamp don't.
+fn get_card_stats(conn: &Connection, deck_id: Option<i32>, since: Option<NaiveDateTime>) -> Result<Vec<CardStats>> {
+    debug!("get_card_stats: deck_id={:?} since={:?}", deck_id, since);
+    let mut stmt = conn.prepare(
+        "SELECT f.id, f.front, SUM(l.answer), SUM(NOT l.answer)
+        FROM flashcard_log l JOIN flashcards f ON f.id = l.question_id
+        WHERE (?1 IS NULL OR f.deck_id = ?1) AND (?2 IS NULL OR l.reviewed_at >= ?2)
+        GROUP BY f.id
+        ORDER BY SUM(NOT l.answer) DESC, f.id"
+    )?;
+
+    let rows = stmt.query_map(params![deck_id, since], |row| {
+        Ok(CardStats {
+            id: row.get(0)?,
+            front: row.get(1)?,
+            correct: row.get(2)?,
+            incorrect: row.get(3)?,
+        })
+    })?.collect();
+    rows
+}
+
+// The number of cards and of due cards, in one deck or all of them.
+fn get_card_totals(conn: &Connection, deck_id: Option<i32>, today: &NaiveDate) -> Result<(i32, i32)> {
+    debug!("get_card_totals: deck_id={:?} today={}", deck_id, today);
+    let sql = format!(
+        "SELECT COUNT(*), COALESCE(SUM({} AND {}), 0) FROM flashcards WHERE ?1 IS NULL OR deck_id = ?1",
+        DueFilter::Due.sql("?2"),
+        suspension_sql(false),
+    );
+    conn.query_row(&sql, params![deck_id, today], |row| Ok((row.get(0)?, row.get(1)?)))
+}
+
+/// `stats [deck_name] [--days N] [--summary-only]`: total reviews and accuracy
+/// from the review log, then correct and incorrect counts per card, most
+/// failed first.
+///
+/// `--days N` counts only the last N days of reviews. `--summary-only` prints
+/// just one line, like `Geo: 340 cards, 89% accuracy, 42 due`.
+pub fn stats(conn: &Connection, args: &[String]) {
+    let mut args = args.to_vec();
+    let summary_only = take_flag(&mut args, "--summary-only");
+    let days: Option<u64> = match take_parsed(&mut args, "--days") {
+        Ok(days) => days,
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    let deck_id = match args.get(2) {
+        Some(deck) => match get_deck_id(conn, deck).and_then(|id| get_deck_name(conn, id).map(|_| id)) {
+            Ok(id) => Some(id),
+            Err(rusqlite::Error::QueryReturnedNoRows) => {
+                fail!("No deck with name or id {}", deck);
+                return;
+            },
+            Err(e) => {
+                fail!("Error getting deck id: {}", e);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let since = days.map(|days| {
+        let today = today();
+        today.checked_sub_days(Days::new(days)).unwrap_or(NaiveDate::MIN).and_hms_opt(0, 0, 0).unwrap()
+    });
+    let rows = match get_card_stats(conn, deck_id, since) {
+        Ok(rows) => rows,
+        Err(e) => {
+            fail!("Error reading review history: {}", e);
+            return;
+        }
+    };
+
+    let correct: i32 = rows.iter().map(|row| row.correct).sum();
+    let total: i32 = correct + rows.iter().map(|row| row.incorrect).sum::<i32>();
+
+    if summary_only {
+        let label = match deck_id {
+            Some(id) => match get_deck_name(conn, id) {
+                Ok(name) => name,
+                Err(e) => {
+                    fail!("Error reading deck: {}", e);
+                    return;
+                }
+            },
+            None => "All decks".to_string(),
+        };
+        let (cards, due) = match get_card_totals(conn, deck_id, &today()) {
+            Ok(totals) => totals,
+            Err(e) => {
+                fail!("Error counting cards: {}", e);
+                return;
+            }
+        };
+        let accuracy = match total {
+            0 => "no reviews".to_string(),
+            _ => format!("{:.0}% accuracy", correct as f64 * 100.0 / total as f64),
+        };
+        println!("{}: {} cards, {}, {} due", label, cards, accuracy, due);
+        return;
+    }
+
+    if total == 0 {
+        println!("No reviews recorded");
+        return;
+    }
+    println!("Reviews: {}", total);
+    println!("Accuracy: {:.0}%", correct as f64 * 100.0 / total as f64);
+    println!();
+
+    println!("{:>5}  {:>7}  {:>9}  front", "id", "correct", "incorrect");
+    for row in &rows {
+        println!("{:>5}  {:>7}  {:>9}  {}", row.id, row.correct, row.incorrect, first_line(&row.front));
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SearchSort {
+    // Most matches of the term first.
+    Relevance,
+    Deck,
+    // Least recently reviewed first, never reviewed before that.
+    Reviewed,
+    Level,
+}
+
+impl SearchSort {
+    fn parse(name: &str) -> Option<SearchSort> {
+        match name {
+            "relevance" => Some(SearchSort::Relevance),
+            "deck" => Some(SearchSort::Deck),
+            "reviewed" => Some(SearchSort::Reviewed),
+            "level" => Some(SearchSort::Level),
+            _ => None,
+        }
+    }
+}
+
+struct SearchHit {
+    id: i32,
+    deck: String,
+    front: String,
+    level: i32,
+    last_reviewed: Option<NaiveDateTime>,
+    // How often the term occurs in front and back.
+    matches: usize,
+}
+
+// Cards whose front or back contains `term`, case-insensitively, in id order.
+fn search_cards(conn: &Connection, term: &str) -> Result<Vec<SearchHit>> {
+    debug!("search_cards: term={:?}", term);
+    let pattern = format!("%{}%", term.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+    let mut stmt = conn.prepare(
+        "SELECT f.id, d.name, f.front, f.back, f.level, MAX(l.reviewed_at)
+        FROM flashcards f
+        JOIN decks d ON d.id = f.deck_id
+        LEFT JOIN flashcard_log l ON l.question_id = f.id
+        WHERE f.front LIKE ?1 ESCAPE '\\' OR f.back LIKE ?1 ESCAPE '\\'
+        GROUP BY f.id
+        ORDER BY f.id"
+    )?;
+
+    let term = term.to_lowercase();
+    let rows = stmt.query_map(params![pattern], |row| {
+        let front: String = row.get(2)?;
+        let back: String = row.get(3)?;
+        let matches = front.to_lowercase().matches(&term).count() + back.to_lowercase().matches(&term).count();
+        Ok(SearchHit {
+            id: row.get(0)?,
+            deck: row.get(1)?,
+            front,
+            level: row.get(4)?,
+            last_reviewed: row.get(5)?,
+            matches,
+        })
+    })?.collect();
+    rows
+}
+
+// Stable, so ties stay in id order.
+fn sort_hits(hits: &mut [SearchHit], sort: SearchSort) {
+    match sort {
+        SearchSort::Relevance => hits.sort_by_key(|hit| Reverse(hit.matches)),
+        SearchSort::Deck => hits.sort_by(|a, b| a.deck.cmp(&b.deck)),
+        SearchSort::Reviewed => hits.sort_by_key(|hit| hit.last_reviewed),
+        SearchSort::Level => hits.sort_by_key(|hit| hit.level),
+    }
+}
+
+/// `search <term> [--sort relevance|deck|reviewed|level]`: cards in any deck
+/// whose front or back contains the term, ignoring case.
+pub fn search(conn: &Connection, args: &[String]) {
+    let mut args = args.to_vec();
+    let sort = match take_value(&mut args, "--sort") {
+        Ok(None) => SearchSort::Relevance,
+        Ok(Some(name)) => match SearchSort::parse(&name) {
+            Some(sort) => sort,
+            None => {
+                fail!("Unknown sort: {} (supported: relevance, deck, reviewed, level)", name);
+                return;
+            }
+        },
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    if args.len() < 3 || args[2].is_empty() {
+        fail!("Missing <term>");
+        return;
+    }
+
+    let mut hits = match search_cards(conn, &args[2]) {
+        Ok(hits) => hits,
+        Err(e) => {
+            fail!("Error searching flashcards: {}", e);
+            return;
+        }
+    };
+    sort_hits(&mut hits, sort);
+
+    if hits.is_empty() {
+        println!("No cards match {}", args[2]);
+        return;
+    }
+    println!("{:>5}  {:<12}  {:>5}  {:<16}  front", "id", "deck", "level", "last review");
+    for hit in &hits {
+        let last_reviewed = hit.last_reviewed.map_or("-".to_string(), |at| at.format("%Y-%m-%d %H:%M").to_string());
+        println!("{:>5}  {:<12}  {:>5}  {:<16}  {}", hit.id, hit.deck, hit.level, last_reviewed, first_line(&hit.front));
+    }
+}
+
+/// `peek <card_id>`: shows everything stored about one card.
+pub fn peek(conn: &Connection, args: &[String]) {
+    if args.len() < 3 {
+        fail!("Missing <card_id>");
+        return;
+    }
+
+    let id: i32 = match args[2].parse() {
+        Ok(id) => id,
+        Err(_) => {
+            fail!("Invalid card id: {}", args[2]);
+            return;
+        }
+    };
+
+    let card = match get_flashcard(conn, id) {
+        Ok(card) => card,
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            fail!("No card with id {}", id);
+            return;
+        },
+        Err(e) => {
+            fail!("Error reading flashcard: {}", e);
+            return;
+        }
+    };
+
+    println!("id:     {}", card.id);
+    println!("deck:   {}", card.deck_id);
+    println!("level:  {}", card.level);
+    println!("added:  {}", card.added);
+    println!("next:   {}", card.next);
+    println!("source: {}", card.source.as_deref().unwrap_or("-"));
+    if card.suspended {
+        println!("status: suspended");
+    }
+    println!("front:\n{}", card.front.trim());
+    println!("back:\n{}", card.back.trim());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(id: i32, deck: &str, level: i32, matches: usize, last_reviewed: Option<&str>) -> SearchHit {
+        SearchHit {
+            id,
+            deck: deck.to_string(),
+            front: String::new(),
+            level,
+            last_reviewed: last_reviewed.map(|at| NaiveDateTime::parse_from_str(at, "%Y-%m-%d %H:%M").unwrap()),
+            matches,
+        }
+    }
+
+    #[test]
+    fn search_hits_sort_by_each_key() {
+        let sorted = |sort| {
+            let mut hits = vec![
+                hit(1, "b", 3, 1, None),
+                hit(2, "a", 1, 1, Some("2024-01-03 10:00")),
+                hit(3, "b", 2, 2, Some("2024-01-02 10:00")),
+            ];
+            sort_hits(&mut hits, sort);
+            hits.iter().map(|hit| hit.id).collect::<Vec<i32>>()
+        };
+        assert_eq!(sorted(SearchSort::Relevance), vec![3, 1, 2]);
+        assert_eq!(sorted(SearchSort::Deck), vec![2, 1, 3]);
+        assert_eq!(sorted(SearchSort::Reviewed), vec![1, 3, 2]);
+        assert_eq!(sorted(SearchSort::Level), vec![2, 3, 1]);
+    }
+
+    #[test]
+    fn retention_is_ninety_percent_when_due() {
+        assert!((estimate_retention(10, 10) - 0.9).abs() < 1e-9);
+        assert_eq!(estimate_retention(0, 10), 1.0);
+        assert!(estimate_retention(20, 10) < estimate_retention(5, 10));
+    }
+}
@@ -0,0 +1,294 @@
+use std::fmt;
+
+use chrono::{Days, NaiveDate};
+use log::warn;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Grade {
+    Again,
+    Hard,
+    Good,
+    Easy,
+    // An SM-2 quality from 0 (blackout) to 5 (perfect recall); see `quiz --quality`.
+    Quality(u8),
+}
+
+impl Grade {
+    /// Parses a grade as written in an answers file or typed by the user.
+    pub fn parse(text: &str) -> Option<Grade> {
+        match text.trim().to_lowercase().as_str() {
+            "1" | "o" | "good" => Some(Grade::Good),
+            "2" | "x" | "again" => Some(Grade::Again),
+            "3" | "hard" => Some(Grade::Hard),
+            "4" | "easy" => Some(Grade::Easy),
+            _ => None,
+        }
+    }
+
+    /// Like `parse`, but digits are qualities 0-5; the named grades still work.
+    pub fn parse_quality(text: &str) -> Option<Grade> {
+        match text.trim().parse::<u8>() {
+            Ok(quality) if quality <= 5 => Some(Grade::Quality(quality)),
+            Ok(_) => None,
+            Err(_) => Grade::parse(text),
+        }
+    }
+
+    /// The grade on SM-2's 0-5 quality scale. O and X map to 5 and 2, so
+    /// they keep meaning pass and fail.
+    pub fn quality(&self) -> u8 {
+        match self {
+            Grade::Again => 2,
+            Grade::Hard => 3,
+            Grade::Good | Grade::Easy => 5,
+            Grade::Quality(quality) => *quality,
+        }
+    }
+
+    pub fn is_correct(&self) -> bool {
+        self.quality() >= 3
+    }
+}
+
+impl fmt::Display for Grade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Grade::Again => write!(f, "Again"),
+            Grade::Hard => write!(f, "Hard"),
+            Grade::Good => write!(f, "Good"),
+            Grade::Easy => write!(f, "Easy"),
+            Grade::Quality(quality) => write!(f, "q{}", quality),
+        }
+    }
+}
+
+/// How reviews are scheduled; set with the `scheduler` setting.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scheduler {
+    // Per-card ease factors and intervals, after SuperMemo's SM-2.
+    Sm2,
+    // The fixed ladder of intervals by level in `level_to_date`.
+    Leitner,
+}
+
+impl Scheduler {
+    pub fn parse(name: &str) -> Option<Scheduler> {
+        match name {
+            "sm2" => Some(Scheduler::Sm2),
+            "leitner" => Some(Scheduler::Leitner),
+            _ => None,
+        }
+    }
+}
+
+/// The ease factor new cards start with.
+pub const INITIAL_EASE: f64 = 2.5;
+// SM-2 never lets the ease factor drop below this.
+const MIN_EASE: f64 = 1.3;
+
+/// A card's scheduling state before it's graded.
+#[derive(Clone, Copy, Debug)]
+pub struct CardState {
+    pub level: i32,
+    pub ease_factor: f64,
+    // Correct answers in a row.
+    pub repetitions: i32,
+    // The interval the card was last scheduled with, in days.
+    pub interval_days: i32,
+}
+
+/// The outcome of grading a card: its new scheduling state and next review date.
+pub struct Review {
+    pub level: i32,
+    pub next: NaiveDate,
+    pub ease_factor: f64,
+    pub repetitions: i32,
+    pub interval_days: i32,
+}
+
+/// Levels above this all share the longest interval.
+pub const MAX_LEVEL: i32 = 6;
+
+pub fn level_to_date(level: i32) -> i32 {
+    match level {
+        1 => 1,
+        2 => 4,
+        3 => 10,
+        4 => 25,
+        5 => 50,
+        _ => 1000,
+    }
+}
+
+/// Returns `date` pushed forward by `days`.
+///
+/// Dates past the end of `NaiveDate`'s range are clamped to `NaiveDate::MAX`
+/// instead of panicking, so a pathological interval can't crash a quiz.
+pub fn add_interval(date: NaiveDate, days: u64) -> NaiveDate {
+    match date.checked_add_days(Days::new(days)) {
+        Some(next) => next,
+        None => {
+            warn!("interval of {} days from {} is out of range, clamping to {}", days, date, NaiveDate::MAX);
+            NaiveDate::MAX
+        }
+    }
+}
+
+// Hard keeps the level and stretches the current interval by this factor
+// instead of jumping up the ladder.
+const HARD_FACTOR: f64 = 1.2;
+// Easy skips a level, and is always at least this much longer than Good.
+const EASY_BONUS: f64 = 1.3;
+
+fn scaled(interval: i32, factor: f64) -> i32 {
+    (interval as f64 * factor).ceil() as i32
+}
+
+/// Grades `card` with `scheduler`; the interval is never shorter than
+/// `min_interval_days`.
+pub fn schedule(scheduler: Scheduler, today: NaiveDate, card: &CardState, grade: Grade, min_interval_days: u32) -> Review {
+    let (level, ease_factor, repetitions, interval) = match scheduler {
+        Scheduler::Sm2 => sm2(card, grade),
+        Scheduler::Leitner => {
+            let (level, interval) = leitner(card.level, grade);
+            let repetitions = if grade.is_correct() { card.repetitions + 1 } else { 0 };
+            (level, card.ease_factor, repetitions, interval)
+        },
+    };
+
+    let interval = (interval.max(1) as u64).max(min_interval_days as u64);
+    Review {
+        level,
+        next: add_interval(today, interval),
+        ease_factor,
+        repetitions,
+        interval_days: interval.min(i32::MAX as u64) as i32,
+    }
+}
+
+// Returns the new level and interval.
+//
+// For the same card, Hard < Good < Easy: Hard is capped just below Good's
+// interval, which matters at the top of the ladder where levels stop growing.
+fn leitner(level: i32, grade: Grade) -> (i32, i32) {
+    let grade = match grade {
+        Grade::Quality(0..=2) => Grade::Again,
+        Grade::Quality(3) => Grade::Hard,
+        Grade::Quality(4) => Grade::Good,
+        Grade::Quality(_) => Grade::Easy,
+        grade => grade,
+    };
+
+    let good = level_to_date(level + 1);
+    match grade {
+        Grade::Again => (if level > 1 { level - 1 } else { 1 }, level_to_date(level)),
+        Grade::Hard => (level, scaled(level_to_date(level), HARD_FACTOR).min(good - 1).max(1)),
+        Grade::Good | Grade::Quality(_) => (level + 1, good),
+        Grade::Easy => (level + 2, level_to_date(level + 2).max(scaled(good, EASY_BONUS))),
+    }
+}
+
+// Returns the new level, ease factor, repetitions and interval.
+//
+// Every grade nudges the ease factor: up for quality 5, down for 3 and below.
+// A failed card (quality under 3) starts over with a 1-day interval; a passed
+// one waits 1 day, then 6, then its last interval times the ease factor.
+// Easy is quality 5 with a bonus on the interval. The level is the streak of
+// correct answers plus one, so it still reads as how well a card is known.
+fn sm2(card: &CardState, grade: Grade) -> (i32, f64, i32, i32) {
+    let lapse = 5.0 - grade.quality() as f64;
+    let ease = (card.ease_factor + 0.1 - lapse * (0.08 + lapse * 0.02)).max(MIN_EASE);
+
+    if !grade.is_correct() {
+        return (1, ease, 0, 1);
+    }
+
+    let repetitions = card.repetitions + 1;
+    let interval = match repetitions {
+        1 => 1,
+        2 => 6,
+        _ => (card.interval_days.max(1) as f64 * ease).round() as i32,
+    };
+    let interval = if grade == Grade::Easy { scaled(interval, EASY_BONUS) } else { interval };
+    (repetitions + 1, ease, repetitions, interval)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn card(level: i32) -> CardState {
+        CardState { level, ease_factor: INITIAL_EASE, repetitions: level - 1, interval_days: level_to_date(level) }
+    }
+
+    #[test]
+    fn add_interval_adds_days() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        assert_eq!(add_interval(date, 10), NaiveDate::from_ymd_opt(2024, 1, 11).unwrap());
+    }
+
+    #[test]
+    fn schedule_respects_min_interval() {
+        let today = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let schedule = |grade, min| schedule(Scheduler::Leitner, today, &card(1), grade, min);
+        assert_eq!(schedule(Grade::Again, 1).next, NaiveDate::from_ymd_opt(2024, 1, 2).unwrap());
+        assert_eq!(schedule(Grade::Again, 3).next, NaiveDate::from_ymd_opt(2024, 1, 4).unwrap());
+        assert_eq!(schedule(Grade::Good, 3).next, NaiveDate::from_ymd_opt(2024, 1, 5).unwrap());
+    }
+
+    #[test]
+    fn hard_is_shorter_than_good_is_shorter_than_easy() {
+        let today = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        for level in 1..=8 {
+            let schedule = |grade| schedule(Scheduler::Leitner, today, &card(level), grade, 1);
+            let hard = schedule(Grade::Hard);
+            let good = schedule(Grade::Good);
+            let easy = schedule(Grade::Easy);
+            assert!(hard.next < good.next, "level {}: hard {} >= good {}", level, hard.next, good.next);
+            assert!(good.next < easy.next, "level {}: good {} >= easy {}", level, good.next, easy.next);
+            assert_eq!(hard.level, level);
+        }
+    }
+
+    #[test]
+    fn sm2_grows_intervals_by_ease() {
+        let today = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let mut state = CardState { level: 1, ease_factor: INITIAL_EASE, repetitions: 0, interval_days: 0 };
+        let mut intervals = Vec::new();
+        for _ in 0..4 {
+            let review = schedule(Scheduler::Sm2, today, &state, Grade::Good, 1);
+            intervals.push(review.interval_days);
+            state = CardState { level: review.level, ease_factor: review.ease_factor, repetitions: review.repetitions, interval_days: review.interval_days };
+        }
+        // Each quality 5 raises the ease by 0.1: 6 * 2.8 = 17, 17 * 2.9 = 49.
+        assert_eq!(intervals, vec![1, 6, 17, 49]);
+        assert_eq!(state.level, 5);
+
+        let lapse = schedule(Scheduler::Sm2, today, &state, Grade::Again, 1);
+        assert_eq!((lapse.level, lapse.repetitions, lapse.interval_days), (1, 0, 1));
+        assert!(lapse.ease_factor < state.ease_factor);
+    }
+
+    #[test]
+    fn sm2_ease_has_a_floor() {
+        let today = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let state = CardState { level: 1, ease_factor: MIN_EASE, repetitions: 0, interval_days: 1 };
+        assert_eq!(schedule(Scheduler::Sm2, today, &state, Grade::Quality(0), 1).ease_factor, MIN_EASE);
+    }
+
+    #[test]
+    fn sm2_orders_hard_good_easy() {
+        let today = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let state = CardState { level: 4, ease_factor: INITIAL_EASE, repetitions: 3, interval_days: 15 };
+        let days = |grade| schedule(Scheduler::Sm2, today, &state, grade, 1).interval_days;
+        assert!(days(Grade::Hard) < days(Grade::Good));
+        assert!(days(Grade::Good) < days(Grade::Easy));
+        assert_eq!(days(Grade::Quality(5)), days(Grade::Good));
+    }
+
+    #[test]
+    fn add_interval_clamps_absurd_interval() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        assert_eq!(add_interval(date, u64::MAX), NaiveDate::MAX);
+    }
+}
@@ -0,0 +1,108 @@
+// `quiz --report <file>`: a Markdown summary of one session, for a study
+// journal or a tutor. It's written once the session ends, from the grades
+// given during it.
+
+use chrono::NaiveDateTime;
+
+use crate::scheduler::Grade;
+
+/// One session as the report shows it.
+pub struct SessionReport {
+    pub started_at: NaiveDateTime,
+    pub ended_at: NaiveDateTime,
+    pub decks: String,
+    // Graded cards in the order they came up: id, the side shown first, grade.
+    pub results: Vec<(i32, String, Grade)>,
+    // `--practice`: the grades weren't applied.
+    pub practice: bool,
+}
+
+impl SessionReport {
+    fn correct(&self) -> usize {
+        self.results.iter().filter(|(_, _, grade)| grade.is_correct()).count()
+    }
+
+    // The longest run of correct answers.
+    fn best_streak(&self) -> usize {
+        let mut best = 0;
+        let mut run = 0;
+        for (_, _, grade) in &self.results {
+            run = if grade.is_correct() { run + 1 } else { 0 };
+            best = best.max(run);
+        }
+        best
+    }
+}
+
+// Table cells are one line, and a `|` would end the cell.
+fn cell(text: &str) -> String {
+    text.trim().lines().next().unwrap_or("").replace('|', "\\|")
+}
+
+pub fn to_markdown(report: &SessionReport) -> String {
+    let mut out = format!("# Study session: {}\n\n", report.started_at.format("%Y-%m-%d %H:%M"));
+    if report.practice {
+        out.push_str("Practice session: grades weren't applied.\n\n");
+    }
+
+    let total = report.results.len();
+    let correct = report.correct();
+    out.push_str(&format!("- Decks: {}\n", report.decks));
+    out.push_str(&format!("- Duration: {} min\n", (report.ended_at - report.started_at).num_minutes()));
+    out.push_str(&format!("- Cards reviewed: {}\n", total));
+    if total > 0 {
+        out.push_str(&format!("- Accuracy: {:.0}% ({} of {})\n", correct as f64 * 100.0 / total as f64, correct, total));
+        out.push_str(&format!("- Best streak: {} correct in a row\n", report.best_streak()));
+    }
+
+    out.push_str("\n## Cards\n\n");
+    if total == 0 {
+        out.push_str("No cards were graded.\n");
+    } else {
+        out.push_str("| # | Card | Grade |\n|---|------|-------|\n");
+        for (i, (_, front, grade)) in report.results.iter().enumerate() {
+            out.push_str(&format!("| {} | {} | {} |\n", i + 1, cell(front), grade));
+        }
+    }
+
+    out.push_str("\n## Missed\n\n");
+    let missed: Vec<&(i32, String, Grade)> = report.results.iter().filter(|(_, _, grade)| !grade.is_correct()).collect();
+    if missed.is_empty() {
+        out.push_str("None.\n");
+    }
+    for (id, front, _) in missed {
+        out.push_str(&format!("- {} (card {})\n", cell(front), id));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    #[test]
+    fn reports_accuracy_streak_and_missed_cards() {
+        let at = |minute| NaiveDate::from_ymd_opt(2024, 1, 10).unwrap().and_hms_opt(9, minute, 0).unwrap();
+        let report = SessionReport {
+            started_at: at(0),
+            ended_at: at(6),
+            decks: "Geo".to_string(),
+            results: vec![
+                (1, "Capital of France?".to_string(), Grade::Good),
+                (2, "A | B\nsecond line".to_string(), Grade::Again),
+                (3, "Capital of Spain?".to_string(), Grade::Easy),
+                (4, "Capital of Italy?".to_string(), Grade::Hard),
+            ],
+            practice: false,
+        };
+        let markdown = to_markdown(&report);
+        assert!(markdown.starts_with("# Study session: 2024-01-10 09:00\n"));
+        assert!(markdown.contains("- Duration: 6 min\n"));
+        assert!(markdown.contains("- Accuracy: 75% (3 of 4)\n"));
+        assert!(markdown.contains("- Best streak: 2 correct in a row\n"));
+        assert!(markdown.contains("| 2 | A \\| B | Again |\n"));
+        assert!(markdown.ends_with("## Missed\n\n- A \\| B (card 2)\n"));
+    }
+}
@@ -0,0 +1,60 @@
+// Starter decks for `init --from`: card files bundled into the binary, so
+// a new database can have something to quiz right away.
+//
+// Each is a card file in the flash format under `starters/`, imported the
+// way `add cards` imports one.
+
+pub struct Starter {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub text: &'static str,
+}
+
+pub const STARTERS: &[Starter] = &[
+    Starter {
+        name: "howto",
+        description: "How to use flash: cards that teach the commands",
+        text: include_str!("../starters/howto.md"),
+    },
+    Starter {
+        name: "capitals",
+        description: "World capitals, tagged by continent",
+        text: include_str!("../starters/capitals.md"),
+    },
+];
+
+pub fn find(name: &str) -> Result<&'static Starter, String> {
+    match STARTERS.iter().find(|starter| starter.name == name) {
+        Some(starter) => Ok(starter),
+        None => {
+            let names: Vec<&str> = STARTERS.iter().map(|starter| starter.name).collect();
+            Err(format!("Unknown template: {} (supported: {})", name, names.join(", ")))
+        }
+    }
+}
+
+/// `init --list-templates`: the starter decks `init --from` can add.
+pub fn list() {
+    for starter in STARTERS {
+        println!("{:<10}  {}", starter.name, starter.description);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format;
+    use crate::parse::{parse_cards, EmptySides};
+
+    #[test]
+    fn starters_parse_without_errors() {
+        let flash = format::find("flash").unwrap();
+        let today = chrono::NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+        for starter in STARTERS {
+            let file = flash.parse(starter.text).unwrap();
+            let parsed = parse_cards(1, file.cards, &today, false, starter.name, EmptySides::Reject);
+            assert!(parsed.errors.is_empty(), "{}: {:?}", starter.name, parsed.errors.iter().map(|e| &e.message).collect::<Vec<_>>());
+            assert!(!parsed.cards.is_empty());
+        }
+    }
+}
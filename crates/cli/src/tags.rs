@@ -0,0 +1,104 @@
+// Tags: labels on cards for studying or listing part of a deck, with
+// `quiz --tag` and `list --tag`.
+//
+// Tags are set by a `tags:` line in a card file block or with the `tag`
+// command, and kept one row per tag in `card_tags`. A tag is a word without
+// whitespace or commas; case matters.
+
+use rusqlite::{params, Connection, Result};
+use log::debug;
+
+use crate::get_flashcard;
+
+/// Splits a comma or whitespace separated list into tags, sorted and
+/// without duplicates.
+pub fn parse_tags(text: &str) -> Vec<String> {
+    let mut tags: Vec<String> = text
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_string)
+        .collect();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+/// A column expression for the tags of the card whose id is `id_column`,
+/// read back with `from_sql`.
+pub fn tags_sql(id_column: &str) -> String {
+    format!("(SELECT group_concat(tag, ' ') FROM card_tags WHERE card_id = {})", id_column)
+}
+
+pub fn from_sql(tags: Option<String>) -> Vec<String> {
+    tags.map_or_else(Vec::new, |tags| parse_tags(&tags))
+}
+
+/// SQL predicate matching cards, by `id_column`, tagged with the tag bound to
+/// `param`. Cards without tags never match.
+pub fn tagged_sql(id_column: &str, param: &str) -> String {
+    format!("{} IN (SELECT card_id FROM card_tags WHERE tag = {})", id_column, param)
+}
+
+/// Adds `tags` to a card; tags it already has are left alone.
+pub fn insert_tags(conn: &Connection, card_id: i32, tags: &[String]) -> Result<usize> {
+    debug!("insert_tags: card_id={} tags={:?}", card_id, tags);
+    let mut stmt = conn.prepare("INSERT OR IGNORE INTO card_tags (card_id, tag) VALUES (?1, ?2)")?;
+    let mut added = 0;
+    for tag in tags {
+        added += stmt.execute(params![card_id, tag])?;
+    }
+    Ok(added)
+}
+
+/// Replaces a card's tags with `tags`.
+pub fn set_tags(conn: &Connection, card_id: i32, tags: &[String]) -> Result<()> {
+    debug!("set_tags: card_id={} tags={:?}", card_id, tags);
+    conn.execute("DELETE FROM card_tags WHERE card_id = ?1", params![card_id])?;
+    insert_tags(conn, card_id, tags)?;
+    Ok(())
+}
+
+/// `tag <card_id> <tag>...`: adds tags to a card.
+pub fn tag(conn: &Connection, args: &[String]) {
+    if args.len() < 4 {
+        fail!("Usage: tag <card_id> <tag>...");
+        return;
+    }
+
+    let id: i32 = match args[2].parse() {
+        Ok(id) => id,
+        Err(_) => {
+            fail!("Invalid card id: {}", args[2]);
+            return;
+        }
+    };
+
+    match get_flashcard(conn, id) {
+        Ok(_) => {},
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            fail!("No card with id {}", id);
+            return;
+        },
+        Err(e) => {
+            fail!("Error reading flashcard: {}", e);
+            return;
+        }
+    }
+
+    let tags = parse_tags(&args[3..].join(" "));
+    match insert_tags(conn, id, &tags) {
+        Ok(added) => println!("Card {}: {} tags added", id, added),
+        Err(e) => fail!("Error tagging card: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_split_on_commas_and_whitespace() {
+        assert_eq!(parse_tags("verb, irregular  verb,,past"), vec!["irregular", "past", "verb"]);
+        assert!(parse_tags(" , ").is_empty());
+    }
+}
@@ -0,0 +1,262 @@
+// Note types: reusable card templates with named fields.
+//
+// A note type such as "capital" declares fields (`country`, `capital`) and
+// front/back templates (`What is the capital of {country}?`, `{capital}`).
+// Cards created from a note type store their field values in `card_fields`
+// and are rendered from the templates at quiz time, so editing a note type
+// changes every card built from it.
+
+use rusqlite::{params, Connection, Result};
+use log::debug;
+
+use crate::scheduler::INITIAL_EASE;
+use crate::{get_deck_id, insert_flashcard, today, Flashcard, Savepoint};
+
+pub struct NoteType {
+    pub id: i32,
+    pub name: String,
+    pub fields: Vec<String>,
+    pub front: String,
+    pub back: String,
+}
+
+/// Substitutes `{field}` placeholders in `template`; unknown placeholders are left as-is.
+///
+/// The template is scanned once, so braces inside a field's value are kept
+/// as typed rather than read as placeholders.
+pub fn render(template: &str, fields: &[(String, String)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let placeholder = rest[1..].find('}').and_then(|end| {
+            let name = &rest[1..end + 1];
+            fields.iter().find(|(field, _)| field == name).map(|(_, value)| (value, end + 2))
+        });
+        match placeholder {
+            Some((value, len)) => {
+                out.push_str(value);
+                rest = &rest[len..];
+            },
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn insert_note_type(conn: &Connection, note_type: &NoteType) -> Result<()> {
+    debug!("insert_note_type: name={} fields={:?}", note_type.name, note_type.fields);
+    conn.execute(
+        "INSERT INTO note_types (name, fields, front_template, back_template) VALUES (?1, ?2, ?3, ?4)",
+        params![note_type.name, note_type.fields.join(","), note_type.front, note_type.back],
+    )?;
+    Ok(())
+}
+
+fn note_type_from_row(row: &rusqlite::Row) -> Result<NoteType> {
+    let fields: String = row.get(2)?;
+    Ok(NoteType {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        fields: fields.split(',').map(|f| f.to_string()).collect(),
+        front: row.get(3)?,
+        back: row.get(4)?,
+    })
+}
+
+fn get_note_type(conn: &Connection, id: i32) -> Result<NoteType> {
+    debug!("get_note_type: id={}", id);
+    let mut stmt = conn.prepare("SELECT id, name, fields, front_template, back_template FROM note_types WHERE id = ?1")?;
+    stmt.query_row(params![id], note_type_from_row)
+}
+
+fn get_note_type_from_name(conn: &Connection, name: &str) -> Result<NoteType> {
+    debug!("get_note_type_from_name: name={}", name);
+    let mut stmt = conn.prepare("SELECT id, name, fields, front_template, back_template FROM note_types WHERE name = ?1")?;
+    stmt.query_row(params![name], note_type_from_row)
+}
+
+fn insert_card_fields(conn: &Connection, card_id: i32, fields: &[(String, String)]) -> Result<()> {
+    debug!("insert_card_fields: card_id={} fields={}", card_id, fields.len());
+    for (name, value) in fields {
+        conn.execute(
+            "INSERT INTO card_fields (card_id, name, value) VALUES (?1, ?2, ?3)",
+            params![card_id, name, value],
+        )?;
+    }
+    Ok(())
+}
+
+// Adds a card built from a note type along with its field values, in one
+// savepoint, so a card is never left without the fields its templates need.
+fn insert_note(conn: &Connection, card: &Flashcard, fields: &[(String, String)]) -> Result<i32> {
+    debug!("insert_note: deck_id={} note_type_id={:?}", card.deck_id, card.note_type_id);
+    let tx = Savepoint::new(conn, "add_note")?;
+    insert_flashcard(&tx, card)?;
+    let card_id = tx.last_insert_rowid() as i32;
+    insert_card_fields(&tx, card_id, fields)?;
+    tx.commit()?;
+    Ok(card_id)
+}
+
+fn get_card_fields(conn: &Connection, card_id: i32) -> Result<Vec<(String, String)>> {
+    debug!("get_card_fields: card_id={}", card_id);
+    let mut stmt = conn.prepare("SELECT name, value FROM card_fields WHERE card_id = ?1")?;
+    let fields = stmt.query_map(params![card_id], |row| Ok((row.get(0)?, row.get(1)?)))?.collect();
+    fields
+}
+
+/// Renders the front and back of a card that was created from a note type.
+pub fn render_card(conn: &Connection, card_id: i32, note_type_id: i32) -> Result<(String, String)> {
+    let note_type = get_note_type(conn, note_type_id)?;
+    let fields = get_card_fields(conn, card_id)?;
+    Ok((render(&note_type.front, &fields), render(&note_type.back, &fields)))
+}
+
+/// `add note-type <name> <field1,field2,...> <front_template> <back_template>`
+pub fn add_note_type(conn: &Connection, args: &[String]) {
+    if args.len() < 7 {
+        fail!("Usage: add note-type <name> <field1,field2,...> <front_template> <back_template>");
+        return;
+    }
+
+    let fields: Vec<String> = args[4].split(',')
+        .map(|f| f.trim().to_string())
+        .filter(|f| !f.is_empty())
+        .collect();
+    if fields.is_empty() {
+        fail!("A note type needs at least one field");
+        return;
+    }
+
+    let note_type = NoteType {
+        id: -1, // dummy value
+        name: args[3].to_string(),
+        fields,
+        front: args[5].to_string(),
+        back: args[6].to_string(),
+    };
+
+    match insert_note_type(conn, &note_type) {
+        Ok(_) => {
+            println!("Note type added: {}", note_type.name);
+        },
+        Err(e) => {
+            fail!("Error adding note type: {}", e);
+        }
+    }
+}
+
+/// `add note <deck_name> <note_type> <field>=<value>...`
+pub fn add_note(conn: &Connection, args: &[String]) {
+    if args.len() < 5 {
+        fail!("Usage: add note <deck_name> <note_type> <field>=<value>...");
+        return;
+    }
+
+    let deck_id = match get_deck_id(conn, &args[3]) {
+        Ok(id) => id,
+        Err(e) => {
+            fail!("Error getting deck id: {}", e);
+            return;
+        }
+    };
+
+    let note_type = match get_note_type_from_name(conn, &args[4]) {
+        Ok(note_type) => note_type,
+        Err(e) => {
+            fail!("Error getting note type: {}", e);
+            return;
+        }
+    };
+
+    let mut fields: Vec<(String, String)> = Vec::new();
+    for arg in &args[5..] {
+        let (name, value) = match arg.split_once('=') {
+            Some(pair) => pair,
+            None => {
+                fail!("Expected <field>=<value>, got: {}", arg);
+                return;
+            }
+        };
+        if !note_type.fields.iter().any(|f| f == name) {
+            fail!("Unknown field for note type {}: {}", note_type.name, name);
+            return;
+        }
+        if fields.iter().any(|(f, _)| f == name) {
+            fail!("Field given more than once: {}", name);
+            return;
+        }
+        fields.push((name.to_string(), value.to_string()));
+    }
+
+    for name in &note_type.fields {
+        if !fields.iter().any(|(f, _)| f == name) {
+            fail!("Missing field: {}", name);
+            return;
+        }
+    }
+
+    // front/back hold a rendered snapshot so commands that don't know about
+    // note types (export, ...) still see meaningful text.
+    let today = today();
+    let card = Flashcard {
+        id: -1, // dummy value
+        deck_id,
+        front: render(&note_type.front, &fields),
+        back: render(&note_type.back, &fields),
+        added: today,
+        next: today,
+        level: 1,
+        ease_factor: INITIAL_EASE,
+        repetitions: 0,
+        interval_days: 0,
+        note_type_id: Some(note_type.id),
+        source: None,
+        suspended: false,
+        tags: Vec::new(),
+    };
+
+    match insert_note(conn, &card, &fields) {
+        Ok(_) => {
+            println!("Flashcard added: {}", card.front);
+        },
+        Err(e) => {
+            fail!("Error adding flashcard: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn render_substitutes_fields() {
+        let fields = fields(&[("country", "France"), ("capital", "Paris")]);
+        assert_eq!(render("What is the capital of {country}?", &fields), "What is the capital of France?");
+        assert_eq!(render("{capital}, {country}", &fields), "Paris, France");
+    }
+
+    #[test]
+    fn render_leaves_unknown_placeholders() {
+        let fields = fields(&[("country", "France")]);
+        assert_eq!(render("{country} / {capital}", &fields), "France / {capital}");
+        assert_eq!(render("{{country}}", &fields), "{France}");
+    }
+
+    #[test]
+    fn render_keeps_braces_in_values() {
+        let fields = fields(&[("country", "{capital}"), ("capital", "Paris")]);
+        assert_eq!(render("{country} / {capital}", &fields), "{capital} / Paris");
+    }
+}
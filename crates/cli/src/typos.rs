@@ -0,0 +1,130 @@
+// Wrong typed answers captured by `quiz --type-answer --capture-typos`, and
+// the `typos` report over them.
+//
+// Answers are stored as typed, minus surrounding whitespace; the report groups
+// them the same way typed answers are compared, so `Pari` and `pari ` count
+// as one mistake.
+
+use std::collections::HashMap;
+
+use rusqlite::{params, Connection, Result};
+use chrono::Local;
+use log::debug;
+
+use crate::args::take_parsed;
+use crate::{answer, first_line, get_deck_id};
+
+pub fn capture(conn: &Connection, card_id: i32, typed: &str) -> Result<()> {
+    debug!("capture_typo: card_id={}", card_id);
+    conn.execute(
+        "INSERT INTO typos (card_id, answer, captured_at) VALUES (?1, ?2, ?3)",
+        params![card_id, typed.trim(), Local::now().naive_local()],
+    )?;
+    Ok(())
+}
+
+struct TypoRow {
+    card_id: i32,
+    front: String,
+    answer: String,
+}
+
+fn get_typos(conn: &Connection, deck_id: i32) -> Result<Vec<TypoRow>> {
+    debug!("get_typos: deck_id={}", deck_id);
+    let mut stmt = conn.prepare(
+        "SELECT t.card_id, f.front, t.answer
+        FROM typos t JOIN flashcards f ON f.id = t.card_id
+        WHERE f.deck_id = ?1
+        ORDER BY t.card_id, t.captured_at"
+    )?;
+    let rows = stmt.query_map(params![deck_id], |row| {
+        Ok(TypoRow {
+            card_id: row.get(0)?,
+            front: row.get(1)?,
+            answer: row.get(2)?,
+        })
+    })?.collect();
+    rows
+}
+
+/// Counts each card's wrong answers, grouping answers that compare equal;
+/// each group is shown as its first spelling. Most common first.
+fn tally(rows: &[TypoRow]) -> Vec<(i32, String, u32)> {
+    let mut counts: Vec<(i32, String, u32)> = Vec::new();
+    let mut index: HashMap<(i32, String), usize> = HashMap::new();
+    for row in rows {
+        let key = (row.card_id, answer::comparable(&row.answer));
+        match index.get(&key) {
+            Some(&i) => counts[i].2 += 1,
+            None => {
+                index.insert(key, counts.len());
+                counts.push((row.card_id, row.answer.clone(), 1));
+            }
+        }
+    }
+    counts.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+    counts
+}
+
+/// `typos <deck_name> [--limit 20]`: the most common wrong typed answers.
+pub fn typos(conn: &Connection, args: &[String]) {
+    let mut args = args.to_vec();
+
+    let limit: usize = match take_parsed(&mut args, "--limit") {
+        Ok(limit) => limit.unwrap_or(20),
+        Err(e) => {
+            fail!("{}", e);
+            return;
+        }
+    };
+
+    if args.len() < 3 {
+        fail!("Missing <deck_name>");
+        return;
+    }
+
+    let deck_id = match get_deck_id(conn, &args[2]) {
+        Ok(id) => id,
+        Err(e) => {
+            fail!("Error getting deck id: {}", e);
+            return;
+        }
+    };
+
+    let rows = match get_typos(conn, deck_id) {
+        Ok(rows) => rows,
+        Err(e) => {
+            fail!("Error reading typos: {}", e);
+            return;
+        }
+    };
+    if rows.is_empty() {
+        println!("No wrong answers captured for deck {}", args[2]);
+        return;
+    }
+
+    println!("{:>5}  {:>5}  {:<24}  front", "id", "times", "answer");
+    for (card_id, answer, count) in tally(&rows).into_iter().take(limit) {
+        let front = rows.iter().find(|row| row.card_id == card_id).map_or("", |row| first_line(&row.front));
+        println!("{:>5}  {:>5}  {:<24}  {}", card_id, count, answer, front);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(card_id: i32, answer: &str) -> TypoRow {
+        TypoRow { card_id, front: String::new(), answer: answer.to_string() }
+    }
+
+    #[test]
+    fn tally_groups_equal_answers_per_card() {
+        let rows = vec![row(1, "Pari"), row(1, "pari"), row(1, "Lyon"), row(2, "pari")];
+        assert_eq!(tally(&rows), vec![
+            (1, "Pari".to_string(), 2),
+            (1, "Lyon".to_string(), 1),
+            (2, "pari".to_string(), 1),
+        ]);
+    }
+}
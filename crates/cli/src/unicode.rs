@@ -0,0 +1,46 @@
+// Unicode normalization, so visually identical text compares equal.
+//
+// Text copied from different sources may be composed (NFC, `é`) or
+// decomposed (NFD, `e` + combining accent), and may use typographic quotes
+// and dashes where the user types plain ones.
+//
+// - Typed answers are always compared in folded form (`fold`), on both sides.
+// - `add cards --normalize-unicode` stores front and back as NFC.
+// - Everything else (storage by default, export, display) keeps the raw text.
+
+use unicode_normalization::UnicodeNormalization;
+
+/// Canonical composition (NFC).
+pub fn nfc(text: &str) -> String {
+    text.nfc().collect()
+}
+
+/// NFC with typographic quotes and dashes replaced by their ASCII forms; for
+/// comparing text, never for storing it.
+pub fn fold(text: &str) -> String {
+    text.nfc()
+        .map(|c| match c {
+            '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{2032}' => '\'',
+            '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{2033}' => '"',
+            '\u{2010}'..='\u{2015}' | '\u{2212}' => '-',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nfc_composes_decomposed_text() {
+        assert_eq!(nfc("Cafe\u{301}"), "Caf\u{e9}");
+        assert_eq!(nfc("Caf\u{e9}"), "Caf\u{e9}");
+    }
+
+    #[test]
+    fn fold_replaces_typographic_punctuation() {
+        assert_eq!(fold("\u{201C}l\u{2019}\u{e9}t\u{e9}\u{201D} \u{2014} x"), "\"l'\u{e9}t\u{e9}\" - x");
+        assert_eq!(fold("e\u{301}"), fold("\u{e9}"));
+    }
+}